@@ -0,0 +1,203 @@
+use crate::config;
+use serde_json::{json, Value};
+
+const CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+/// Where the actor, outbox and post objects are written, under the build directory.
+pub(crate) const DIR: &str = "ap";
+
+/// A post, as the fediverse sees it.
+pub(crate) struct Article<'a> {
+    pub(crate) slug: &'a str,
+    pub(crate) url: &'a str,
+    pub(crate) title: &'a str,
+    pub(crate) published: chrono::DateTime<chrono::Utc>,
+    pub(crate) updated: Option<chrono::DateTime<chrono::Utc>>,
+    /// HTML, with absolute URLs
+    pub(crate) content: &'a str,
+    /// (name, URL of its tag page)
+    pub(crate) tags: Vec<(&'a str, String)>,
+}
+
+pub(crate) fn actor_url(base_url: &str) -> String {
+    format!("{}/{}/actor.json", base_url, DIR)
+}
+
+pub(crate) fn object_url(base_url: &str, slug: &str) -> String {
+    format!("{}/{}/posts/{}.json", base_url, DIR, slug)
+}
+
+fn outbox_url(base_url: &str) -> String {
+    format!("{}/{}/outbox.json", base_url, DIR)
+}
+
+/// The host `base_url` is served from, e.g. `example.com`.
+fn host(base_url: &str) -> &str {
+    let after_scheme = base_url
+        .split_once("://")
+        .map_or(base_url, |(_, rest)| rest);
+    after_scheme.split('/').next().unwrap_or(after_scheme)
+}
+
+/// The WebFinger document, served at `/.well-known/webfinger`, through
+/// which servers find the actor behind `@username@host`.
+pub(crate) fn webfinger(base_url: &str, settings: &config::ActivityPub) -> Value {
+    json!({
+        "subject": format!("acct:{}@{}", settings.username, host(base_url)),
+        "aliases": [actor_url(base_url)],
+        "links": [
+            {
+                "rel": "self",
+                "type": "application/activity+json",
+                "href": actor_url(base_url),
+            },
+            {
+                "rel": "http://webfinger.net/rel/profile-page",
+                "type": "text/html",
+                "href": base_url,
+            },
+        ],
+    })
+}
+
+/// The site as an actor: who to follow, and where its posts are.
+pub(crate) fn actor(
+    base_url: &str,
+    name: &str,
+    summary: &str,
+    settings: &config::ActivityPub,
+    public_key_pem: Option<&str>,
+) -> Value {
+    let id = actor_url(base_url);
+    let mut actor = json!({
+        "@context": [CONTEXT, "https://w3id.org/security/v1"],
+        "id": id,
+        "type": "Person",
+        "preferredUsername": settings.username,
+        "name": name,
+        "summary": summary,
+        "url": base_url,
+        "inbox": settings
+            .inbox
+            .clone()
+            .unwrap_or_else(|| format!("{}/{}/inbox", base_url, DIR)),
+        "outbox": outbox_url(base_url),
+        "manuallyApprovesFollowers": false,
+        "discoverable": true,
+    });
+    if let Some(pem) = public_key_pem {
+        actor["publicKey"] = json!({
+            "id": format!("{}#main-key", id),
+            "owner": id,
+            "publicKeyPem": pem,
+        });
+    }
+    actor
+}
+
+/// A post as an ActivityStreams `Article`.
+pub(crate) fn article(base_url: &str, article: &Article) -> Value {
+    let mut object = json!({
+        "@context": CONTEXT,
+        "id": object_url(base_url, article.slug),
+        "type": "Article",
+        "attributedTo": actor_url(base_url),
+        "name": article.title,
+        "url": article.url,
+        "published": article.published.to_rfc3339(),
+        "content": article.content,
+        "to": [PUBLIC],
+        "tag": article
+            .tags
+            .iter()
+            .map(|(name, href)| json!({
+                "type": "Hashtag",
+                "name": format!("#{}", name.replace(char::is_whitespace, "")),
+                "href": href,
+            }))
+            .collect::<Vec<_>>(),
+    });
+    if let Some(updated) = article.updated {
+        object["updated"] = updated.to_rfc3339().into();
+    }
+    object
+}
+
+/// The actor's outbox: a `Create` activity for each of `articles`, newest first.
+pub(crate) fn outbox(base_url: &str, articles: &[Article]) -> Value {
+    let items: Vec<Value> = articles
+        .iter()
+        .map(|article| {
+            let mut object = self::article(base_url, article);
+            object
+                .as_object_mut()
+                .expect("an article is an object")
+                .remove("@context");
+            json!({
+                "id": format!("{}#create", object_url(base_url, article.slug)),
+                "type": "Create",
+                "actor": actor_url(base_url),
+                "published": article.published.to_rfc3339(),
+                "to": [PUBLIC],
+                "object": object,
+            })
+        })
+        .collect();
+    json!({
+        "@context": CONTEXT,
+        "id": outbox_url(base_url),
+        "type": "OrderedCollection",
+        "totalItems": items.len(),
+        "orderedItems": items,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn describes_the_site_as_an_actor() {
+        let settings = crate::config::ActivityPub::default();
+        let base_url = "https://example.com";
+
+        let webfinger = super::webfinger(base_url, &settings);
+        assert_eq!(webfinger["subject"], "acct:blog@example.com");
+        assert_eq!(
+            webfinger["links"][0]["href"],
+            "https://example.com/ap/actor.json"
+        );
+
+        let actor = super::actor(base_url, "Clark", "A blog", &settings, Some("PEM"));
+        assert_eq!(actor["id"], "https://example.com/ap/actor.json");
+        assert_eq!(actor["preferredUsername"], "blog");
+        assert_eq!(actor["outbox"], "https://example.com/ap/outbox.json");
+        assert_eq!(actor["publicKey"]["owner"], actor["id"]);
+
+        let published = chrono::DateTime::parse_from_rfc3339("2024-05-06T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let articles = [super::Article {
+            slug: "hello",
+            url: "https://example.com/hello.html",
+            title: "Hello",
+            published,
+            updated: None,
+            content: "<p>Hi</p>",
+            tags: vec![(
+                "web dev",
+                "https://example.com/tags/web-dev.html".to_string(),
+            )],
+        }];
+        let outbox = super::outbox(base_url, &articles);
+        assert_eq!(outbox["totalItems"], 1);
+        let create = &outbox["orderedItems"][0];
+        assert_eq!(create["type"], "Create");
+        assert_eq!(
+            create["object"]["id"],
+            "https://example.com/ap/posts/hello.json"
+        );
+        assert_eq!(create["object"]["published"], "2024-05-06T00:00:00+00:00");
+        assert_eq!(create["object"]["tag"][0]["name"], "#webdev");
+        assert!(create["object"].get("@context").is_none());
+    }
+}
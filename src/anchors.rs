@@ -0,0 +1,164 @@
+use maud::{html, Markup};
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use std::collections::HashMap;
+
+/// A heading in a document, for its table of contents.
+pub(crate) struct Heading {
+    /// 1 for `<h1>` through 6 for `<h6>`
+    level: usize,
+    id: String,
+    text: String,
+}
+
+/// Gives every heading an `id` derived from its text, unless it already
+/// has one, and appends a `#` link to it so readers can copy a deep link.
+/// Ids repeated within the document get `-1`, `-2`, ... appended.
+/// Returns the rewritten events along with the headings, in order.
+pub(crate) fn heading_anchors<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+) -> (Vec<Event<'a>>, Vec<Heading>) {
+    let mut out = vec![];
+    let mut headings = vec![];
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut in_heading: Option<(Tag<'a>, Vec<Event<'a>>)> = None;
+
+    for event in events {
+        match (&mut in_heading, event) {
+            (None, Event::Start(tag @ Tag::Heading { .. })) => in_heading = Some((tag, vec![])),
+            (Some(_), Event::End(TagEnd::Heading(level))) => {
+                let (tag, inner) = in_heading.take().expect("inside a heading");
+                let Tag::Heading {
+                    id, classes, attrs, ..
+                } = tag
+                else {
+                    unreachable!("only headings are buffered")
+                };
+
+                let text: String = inner
+                    .iter()
+                    .filter_map(|event| match event {
+                        Event::Text(t) | Event::Code(t) => Some(t.as_ref()),
+                        _ => None,
+                    })
+                    .collect();
+                let id = id.map(|id| id.to_string()).unwrap_or_else(|| {
+                    let base = slugify(&text);
+                    let count = seen.entry(base.clone()).or_default();
+                    *count += 1;
+                    match *count {
+                        1 => base,
+                        n => format!("{}-{}", base, n - 1),
+                    }
+                });
+
+                out.push(Event::Start(Tag::Heading {
+                    level,
+                    id: Some(CowStr::from(id.clone())),
+                    classes,
+                    attrs,
+                }));
+                out.extend(inner);
+                out.push(Event::InlineHtml(
+                    format!(
+                        " <a class=\"heading-anchor\" href=\"#{}\" aria-hidden=\"true\">#</a>",
+                        html! { (id) }.into_string()
+                    )
+                    .into(),
+                ));
+                out.push(Event::End(TagEnd::Heading(level)));
+
+                headings.push(Heading {
+                    level: level as usize,
+                    id,
+                    text,
+                });
+            }
+            (Some((_, inner)), event) => inner.push(event),
+            (None, event) => out.push(event),
+        }
+    }
+
+    (out, headings)
+}
+
+/// Lowercased letters and digits of `text`, with everything else collapsed to dashes.
+fn slugify(text: &str) -> String {
+    let slug = text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug
+    }
+}
+
+/// Nested lists of links to `headings`, each nested under the nearest
+/// shallower heading before it.
+pub(crate) fn table_of_contents(headings: &[Heading]) -> Markup {
+    html! {
+        nav class="toc" aria-label="Table of contents" {
+            (list(headings))
+        }
+    }
+}
+
+fn list(headings: &[Heading]) -> Markup {
+    let top = headings.iter().map(|heading| heading.level).min();
+    // each item is a top level heading followed by the deeper ones under it
+    let mut items: Vec<&[Heading]> = vec![];
+    let mut start = 0;
+    for (i, heading) in headings.iter().enumerate().skip(1) {
+        if Some(heading.level) == top {
+            items.push(&headings[start..i]);
+            start = i;
+        }
+    }
+    if !headings.is_empty() {
+        items.push(&headings[start..]);
+    }
+
+    html! {
+        ul {
+            @for item in items {
+                li {
+                    a href=(format!("#{}", item[0].id)) { (item[0].text) }
+                    @if item.len() > 1 {
+                        (list(&item[1..]))
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::{html, Parser};
+
+    #[test]
+    fn anchors_headings_and_lists_them() {
+        let markdown = "# Intro\n\n## Set `up`\n\n### Details\n\n## Set up\n\n# Intro\n";
+        let (events, headings) = super::heading_anchors(Parser::new(markdown));
+        let mut body = String::new();
+        html::push_html(&mut body, events.into_iter());
+
+        assert!(body.starts_with(
+            "<h1 id=\"intro\">Intro <a class=\"heading-anchor\" href=\"#intro\" aria-hidden=\"true\">#</a></h1>"
+        ));
+        let ids: Vec<&str> = headings.iter().map(|heading| heading.id.as_str()).collect();
+        assert_eq!(ids, ["intro", "set-up", "details", "set-up-1", "intro-1"]);
+
+        assert_eq!(
+            super::table_of_contents(&headings).into_string(),
+            "<nav class=\"toc\" aria-label=\"Table of contents\"><ul>\
+             <li><a href=\"#intro\">Intro</a><ul>\
+             <li><a href=\"#set-up\">Set up</a><ul><li><a href=\"#details\">Details</a></li></ul></li>\
+             <li><a href=\"#set-up-1\">Set up</a></li></ul></li>\
+             <li><a href=\"#intro-1\">Intro</a></li></ul></nav>"
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Stylesheets and scripts linked only from a single post's page,
+/// as hrefs relative to the site root.
+#[derive(Default)]
+pub(crate) struct PostAssets {
+    pub(crate) stylesheets: Vec<String>,
+    pub(crate) scripts: Vec<String>,
+    /// Every file written to the build directory for the post
+    pub(crate) files: Vec<PathBuf>,
+}
+
+/// Copies a post's co-located asset directory (`posts/foo/` next to
+/// `posts/foo.md`) to `build/foo/`, preserving structure.
+/// The stylesheets and scripts the post declares are given a content hash
+/// in their filename so they can be cached forever.
+pub(crate) fn copy_post_assets(
+    assets_dir: &Path,
+    build_dir: &Path,
+    slug: &str,
+    stylesheets: &[String],
+    scripts: &[String],
+) -> Result<PostAssets> {
+    let output_dir = build_dir.join(slug);
+    let mut files = vec![];
+
+    if assets_dir.is_dir() {
+        copy_dir(assets_dir, &output_dir, &mut files)?;
+    }
+
+    let mut fingerprint_all = |names: &[String]| -> Result<Vec<String>> {
+        names
+            .iter()
+            .map(|name| {
+                let fingerprinted = fingerprint(assets_dir, &output_dir, name)?;
+                files.push(output_dir.join(&fingerprinted));
+                Ok(format!("{}/{}", slug, fingerprinted))
+            })
+            .collect()
+    };
+
+    Ok(PostAssets {
+        stylesheets: fingerprint_all(stylesheets)?,
+        scripts: fingerprint_all(scripts)?,
+        files,
+    })
+}
+
+/// Copies static files (stylesheets, images, fonts, the favicon...) into the
+/// root of the build directory, preserving structure. When several of
+/// `static_dirs` have a file at the same path, the last one wins, so a
+/// site's own `static/` can override its theme's.
+/// Returns the paths of the copies.
+pub(crate) fn copy_static(static_dirs: &[PathBuf], build_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for (relative, source) in layered_files(static_dirs)? {
+        let destination = build_dir.join(relative);
+        files.push(destination.clone());
+        if is_up_to_date(&source, &destination) {
+            continue;
+        }
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create {:?}", parent))?;
+        }
+        std::fs::copy(&source, &destination)
+            .with_context(|| format!("Could not copy {:?} to {:?}", source, destination))?;
+    }
+    Ok(files)
+}
+
+/// Every file under each of `dirs`, keyed by its path relative to that dir.
+/// Files in later dirs replace files at the same relative path in earlier ones.
+/// Dirs that don't exist are skipped.
+pub(crate) fn layered_files(dirs: &[PathBuf]) -> Result<BTreeMap<PathBuf, PathBuf>> {
+    fn visit(root: &Path, dir: &Path, files: &mut BTreeMap<PathBuf, PathBuf>) -> Result<()> {
+        for entry in std::fs::read_dir(dir).with_context(|| format!("Could not read {:?}", dir))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                visit(root, &path, files)?;
+            } else {
+                let relative = path.strip_prefix(root).expect("path is under root");
+                files.insert(relative.to_path_buf(), path.clone());
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = BTreeMap::new();
+    for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+        visit(dir, dir, &mut files)?;
+    }
+    Ok(files)
+}
+
+/// Recursively copies `from` into `to`, skipping files whose copy is already
+/// up to date. The paths of the copies are added to `copied`.
+fn copy_dir(from: &Path, to: &Path, copied: &mut Vec<PathBuf>) -> Result<()> {
+    std::fs::create_dir_all(to).with_context(|| format!("Could not create {:?}", to))?;
+
+    for entry in std::fs::read_dir(from).with_context(|| format!("Could not read {:?}", from))? {
+        let entry = entry?;
+        let destination = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &destination, copied)?;
+            continue;
+        }
+        if !is_up_to_date(&entry.path(), &destination) {
+            std::fs::copy(entry.path(), &destination).with_context(|| {
+                format!("Could not copy {:?} to {:?}", entry.path(), destination)
+            })?;
+        }
+        copied.push(destination);
+    }
+
+    Ok(())
+}
+
+/// Whether `destination` has the same size as `source` and was modified no earlier.
+fn is_up_to_date(source: &Path, destination: &Path) -> bool {
+    let (Ok(source), Ok(destination)) = (source.metadata(), destination.metadata()) else {
+        return false;
+    };
+    match (source.modified(), destination.modified()) {
+        (Ok(source_modified), Ok(destination_modified)) => {
+            source.len() == destination.len() && destination_modified >= source_modified
+        }
+        _ => false,
+    }
+}
+
+/// Writes `assets_dir/name` to `output_dir` as `name.<hash>.ext`,
+/// returning the fingerprinted name.
+fn fingerprint(assets_dir: &Path, output_dir: &Path, name: &str) -> Result<String> {
+    let source = assets_dir.join(name);
+    let contents = std::fs::read(&source)
+        .with_context(|| format!("Could not read post asset {:?}", source))?;
+
+    let hash: String = Sha256::digest(&contents)
+        .iter()
+        .take(4)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    let name = Path::new(name);
+    let stem = name
+        .file_stem()
+        .ok_or_else(|| anyhow!("Post asset has no file name: {:?}", source))?
+        .to_string_lossy();
+    let fingerprinted_name = name.with_file_name(match name.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, hash, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, hash),
+    });
+
+    let destination = output_dir.join(&fingerprinted_name);
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Could not create {:?}", parent))?;
+    }
+    std::fs::write(&destination, contents)
+        .with_context(|| format!("Could not write post asset {:?}", destination))?;
+
+    Ok(fingerprinted_name.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn fingerprints_declared_assets() {
+        let dir = crate::test_dir::TestDir::new("assets");
+        let assets_dir = dir.join("posts").join("a-post");
+        let build_dir = dir.join("build");
+        std::fs::create_dir_all(assets_dir.join("css")).unwrap();
+        std::fs::write(assets_dir.join("css").join("post.css"), "p{}").unwrap();
+        std::fs::write(assets_dir.join("cat.png"), "meow").unwrap();
+
+        let assets = super::copy_post_assets(
+            &assets_dir,
+            &build_dir,
+            "a-post",
+            &["css/post.css".to_string()],
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(assets.stylesheets, vec!["a-post/css/post.806db221.css"]);
+        assert!(build_dir.join("a-post/css/post.806db221.css").exists());
+        assert!(build_dir.join("a-post/cat.png").exists());
+    }
+
+    #[test]
+    fn copies_static_files_that_changed() {
+        let dir = crate::test_dir::TestDir::new("static");
+        let theme_static_dir = dir.join("themes/plain/static");
+        let static_dir = dir.join("static");
+        let build_dir = dir.join("build");
+        std::fs::create_dir_all(static_dir.join("fonts")).unwrap();
+        std::fs::create_dir_all(&theme_static_dir).unwrap();
+        std::fs::write(static_dir.join("fonts/a.woff2"), "font").unwrap();
+        std::fs::write(theme_static_dir.join("style.css"), "theme").unwrap();
+        std::fs::write(theme_static_dir.join("theme.css"), "theme").unwrap();
+        std::fs::write(static_dir.join("style.css"), "site").unwrap();
+        let static_dirs = [theme_static_dir, static_dir.clone()];
+
+        super::copy_static(&static_dirs, &build_dir).unwrap();
+        let copy = build_dir.join("fonts/a.woff2");
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "font");
+        assert_eq!(
+            std::fs::read_to_string(build_dir.join("style.css")).unwrap(),
+            "site"
+        );
+        assert_eq!(
+            std::fs::read_to_string(build_dir.join("theme.css")).unwrap(),
+            "theme"
+        );
+
+        // an up to date copy is left alone...
+        std::fs::write(&copy, "kept").unwrap();
+        super::copy_static(&static_dirs, &build_dir).unwrap();
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "kept");
+
+        // ...until the source is newer
+        std::fs::File::options()
+            .write(true)
+            .open(static_dir.join("fonts/a.woff2"))
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+        super::copy_static(&static_dirs, &build_dir).unwrap();
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "font");
+    }
+}
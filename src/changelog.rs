@@ -0,0 +1,123 @@
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Change {
+    Added,
+    Modified,
+}
+
+/// A content file added or edited on a given day, newest first.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Entry {
+    pub(crate) date: String,
+    pub(crate) change: Change,
+    /// Relative to the site directory, e.g. `posts/foo.md`
+    pub(crate) path: String,
+}
+
+/// Additions and edits to files matching `pathspecs` according to `git log`,
+/// at most one entry per file per day.
+pub(crate) fn git_log(site_dir: &Path, pathspecs: &[&str], limit: usize) -> Result<Vec<Entry>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(site_dir)
+        .args([
+            "log",
+            "--relative",
+            "--no-renames",
+            "--name-status",
+            "--diff-filter=AM",
+            "--date=short",
+            "--format=%x00%ad",
+            "--",
+        ])
+        .args(pathspecs)
+        .output()
+        .context("Could not run git")?;
+
+    if !output.status.success() {
+        bail!(
+            "git log failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let mut entries = parse_log(&String::from_utf8_lossy(&output.stdout));
+    entries.truncate(limit);
+    Ok(entries)
+}
+
+fn parse_log(log: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = vec![];
+
+    for commit in log.split('\0').filter(|commit| !commit.trim().is_empty()) {
+        let mut lines = commit.lines();
+        let date = lines.next().unwrap_or_default().trim();
+
+        for line in lines {
+            let Some((status, path)) = line.split_once('\t') else {
+                continue;
+            };
+            let change = match status {
+                "A" => Change::Added,
+                "M" => Change::Modified,
+                _ => continue,
+            };
+
+            // the log is newest first, so an older commit on the same day
+            // only matters if it is the one that added the file
+            if let Some(existing) = entries
+                .iter_mut()
+                .find(|entry| entry.date == date && entry.path == path)
+            {
+                if change == Change::Added {
+                    existing.change = Change::Added;
+                }
+                continue;
+            }
+
+            entries.push(Entry {
+                date: date.to_string(),
+                change,
+                path: path.to_string(),
+            });
+        }
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Change, Entry};
+
+    #[test]
+    fn parses_name_status_log() {
+        let log = "\x002024-03-05\n\nM\tposts/a.md\nM\tpages/about.md\n\
+                   \x002024-03-04\n\nM\tposts/a.md\n\
+                   \x002024-03-04\n\nA\tposts/a.md\n";
+
+        assert_eq!(
+            super::parse_log(log),
+            vec![
+                Entry {
+                    date: "2024-03-05".to_string(),
+                    change: Change::Modified,
+                    path: "posts/a.md".to_string(),
+                },
+                Entry {
+                    date: "2024-03-05".to_string(),
+                    change: Change::Modified,
+                    path: "pages/about.md".to_string(),
+                },
+                Entry {
+                    date: "2024-03-04".to_string(),
+                    change: Change::Added,
+                    path: "posts/a.md".to_string(),
+                },
+            ]
+        );
+    }
+}
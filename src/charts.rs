@@ -0,0 +1,172 @@
+use maud::{html, Markup};
+
+const WIDTH: f64 = 600.0;
+const HEIGHT: f64 = 200.0;
+const PADDING: f64 = 40.0;
+
+/// A line chart of a running total over time, as inline SVG.
+/// `points` must be sorted by date.
+pub(crate) fn cumulative_chart(label: &str, points: &[(chrono::NaiveDate, usize)]) -> Markup {
+    let (Some((first, _)), Some((last, max))) = (points.first(), points.last()) else {
+        return html! {};
+    };
+
+    let days = (*last - *first).num_days().max(1) as f64;
+    let max = (*max).max(1) as f64;
+
+    let polyline = points
+        .iter()
+        .map(|(date, total)| {
+            let x = PADDING + (*date - *first).num_days() as f64 / days * (WIDTH - 2.0 * PADDING);
+            let y = HEIGHT - PADDING - *total as f64 / max * (HEIGHT - 2.0 * PADDING);
+            format!("{:.1},{:.1}", x, y)
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    html! {
+        figure {
+            svg xmlns="http://www.w3.org/2000/svg" viewBox=(format!("0 0 {} {}", WIDTH, HEIGHT)) role="img" aria-label=(label) {
+                line x1=(PADDING) y1=(HEIGHT - PADDING) x2=(WIDTH - PADDING) y2=(HEIGHT - PADDING) stroke="currentColor" {}
+                line x1=(PADDING) y1=(PADDING) x2=(PADDING) y2=(HEIGHT - PADDING) stroke="currentColor" {}
+                polyline points=(polyline) fill="none" stroke="currentColor" stroke-width="2" {}
+                text x=(PADDING) y=(HEIGHT - PADDING / 2.0) font-size="12" fill="currentColor" { (first.format("%Y-%m")) }
+                text x=(WIDTH - PADDING) y=(HEIGHT - PADDING / 2.0) font-size="12" text-anchor="end" fill="currentColor" { (last.format("%Y-%m")) }
+                text x=(PADDING - 4.0) y=(PADDING) font-size="12" text-anchor="end" fill="currentColor" { (max) }
+            }
+            figcaption { (label) }
+        }
+    }
+}
+
+/// A bar chart of `(label, count)` buckets, as inline SVG.
+pub(crate) fn histogram(label: &str, buckets: &[(String, usize)]) -> Markup {
+    if buckets.is_empty() {
+        return html! {};
+    }
+
+    let max = buckets
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or(1)
+        .max(1) as f64;
+    let bar_width = (WIDTH - 2.0 * PADDING) / buckets.len() as f64;
+
+    html! {
+        figure {
+            svg xmlns="http://www.w3.org/2000/svg" viewBox=(format!("0 0 {} {}", WIDTH, HEIGHT)) role="img" aria-label=(label) {
+                line x1=(PADDING) y1=(HEIGHT - PADDING) x2=(WIDTH - PADDING) y2=(HEIGHT - PADDING) stroke="currentColor" {}
+                @for (i, (bucket, count)) in buckets.iter().enumerate() {
+                    @let height = *count as f64 / max * (HEIGHT - 2.0 * PADDING);
+                    @let x = PADDING + i as f64 * bar_width;
+                    rect x=(format!("{:.1}", x + 1.0)) y=(format!("{:.1}", HEIGHT - PADDING - height)) width=(format!("{:.1}", bar_width - 2.0)) height=(format!("{:.1}", height)) fill="currentColor" {
+                        title { (bucket) ": " (count) }
+                    }
+                }
+                text x=(PADDING) y=(HEIGHT - PADDING / 2.0) font-size="12" fill="currentColor" { (buckets[0].0) }
+                text x=(WIDTH - PADDING) y=(HEIGHT - PADDING / 2.0) font-size="12" text-anchor="end" fill="currentColor" { (buckets[buckets.len() - 1].0) }
+                text x=(PADDING - 4.0) y=(PADDING) font-size="12" text-anchor="end" fill="currentColor" { (max) }
+            }
+            figcaption { (label) }
+        }
+    }
+}
+
+/// Counts `values` into consecutive buckets `bucket_size` wide, starting at zero.
+/// Buckets are labelled with their range, e.g. `"500-999"`.
+pub(crate) fn buckets(values: &[usize], bucket_size: usize) -> Vec<(String, usize)> {
+    let Some(max) = values.iter().max() else {
+        return vec![];
+    };
+
+    let mut counts = vec![0; max / bucket_size + 1];
+    for value in values {
+        counts[value / bucket_size] += 1;
+    }
+
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| {
+            (
+                format!("{}-{}", i * bucket_size, (i + 1) * bucket_size - 1),
+                count,
+            )
+        })
+        .collect()
+}
+
+/// Values above this are extreme outliers: more than three interquartile
+/// ranges past the third quartile.
+pub(crate) fn extreme_outlier_threshold(values: &[usize]) -> Option<f64> {
+    if values.len() < 4 {
+        return None;
+    }
+
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    let q1 = sorted[sorted.len() / 4] as f64;
+    let q3 = sorted[sorted.len() * 3 / 4] as f64;
+
+    Some(q3 + 3.0 * (q3 - q1))
+}
+
+/// Running totals of `values` in date order, one point per entry.
+pub(crate) fn running_totals(
+    values: impl IntoIterator<Item = (chrono::NaiveDate, usize)>,
+) -> Vec<(chrono::NaiveDate, usize)> {
+    let mut values: Vec<_> = values.into_iter().collect();
+    values.sort_by_key(|(date, _)| *date);
+
+    let mut total = 0;
+    values
+        .into_iter()
+        .map(|(date, value)| {
+            total += value;
+            (date, total)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn buckets_values() {
+        assert_eq!(
+            super::buckets(&[10, 120, 150, 260], 100),
+            vec![
+                ("0-99".to_string(), 1),
+                ("100-199".to_string(), 2),
+                ("200-299".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_extreme_outliers() {
+        assert_eq!(
+            super::extreme_outlier_threshold(&[100, 200, 300, 400, 5000]),
+            Some(1000.0)
+        );
+        assert_eq!(super::extreme_outlier_threshold(&[100, 200]), None);
+    }
+
+    #[test]
+    fn computes_running_totals_in_date_order() {
+        let date = |s| chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+
+        assert_eq!(
+            super::running_totals([
+                (date("2024-01-01"), 10),
+                (date("2022-01-01"), 5),
+                (date("2023-01-01"), 1),
+            ]),
+            vec![
+                (date("2022-01-01"), 5),
+                (date("2023-01-01"), 6),
+                (date("2024-01-01"), 16),
+            ]
+        );
+    }
+}
@@ -0,0 +1,556 @@
+use crate::config::{CommentEmbed, CommentProvider};
+use anyhow::{anyhow, Context, Result};
+use maud::{html, Markup};
+use pulldown_cmark::{Event, Parser};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// How long fetched replies are reused before asking the remote server again.
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Where a post was announced, and so where its replies live.
+pub(crate) enum Source<'a> {
+    /// A status URL like `https://mastodon.social/@someone/1234`
+    Mastodon(&'a str),
+    /// A post URL like `https://bsky.app/profile/someone.bsky.social/post/3k...`
+    /// or its `at://` URI
+    Bluesky(&'a str),
+}
+
+impl Source<'_> {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Source::Mastodon(_) => "Mastodon",
+            Source::Bluesky(_) => "Bluesky",
+        }
+    }
+
+    /// The page readers should visit to add their own reply.
+    pub(crate) fn reply_url(&self) -> String {
+        match self {
+            Source::Mastodon(url) => url.to_string(),
+            Source::Bluesky(url) => match bluesky_at_uri(url) {
+                Ok((authority, rkey)) => bluesky_web_url(&authority, &rkey),
+                Err(_) => url.to_string(),
+            },
+        }
+    }
+
+    fn fetch(&self) -> Result<Vec<Comment>> {
+        match self {
+            Source::Mastodon(url) => fetch_mastodon_replies(url),
+            Source::Bluesky(url) => fetch_bluesky_replies(url),
+        }
+    }
+}
+
+/// The script that loads `settings`' comment thread in its place. The
+/// thread is matched to the post by the page it's on.
+pub(crate) fn embed(settings: &CommentEmbed) -> Markup {
+    html! {
+        div class="comment-embed" {
+            @match settings.provider {
+                CommentProvider::Giscus => script
+                    src="https://giscus.app/client.js"
+                    data-repo=(settings.repo)
+                    data-repo-id=[settings.repo_id.as_deref()]
+                    data-category=[settings.category.as_deref()]
+                    data-category-id=[settings.category_id.as_deref()]
+                    data-mapping=(settings.mapping)
+                    data-theme=(settings.theme.as_deref().unwrap_or("preferred_color_scheme"))
+                    crossorigin="anonymous"
+                    async {},
+                CommentProvider::Utterances => script
+                    src="https://utteranc.es/client.js"
+                    repo=(settings.repo)
+                    issue-term=(settings.mapping)
+                    theme=(settings.theme.as_deref().unwrap_or("preferred-color-scheme"))
+                    crossorigin="anonymous"
+                    async {},
+            }
+        }
+    }
+}
+
+/// A reply to a post, fetched from wherever the post was announced.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub(crate) struct Comment {
+    pub(crate) author: String,
+    pub(crate) author_url: String,
+    pub(crate) url: String,
+    pub(crate) created_at: String,
+    /// Plain text; remote HTML is never rendered as-is.
+    pub(crate) text: String,
+}
+
+#[derive(Deserialize)]
+struct MastodonContext {
+    descendants: Vec<MastodonStatus>,
+}
+
+#[derive(Deserialize)]
+struct MastodonStatus {
+    url: Option<String>,
+    uri: String,
+    created_at: String,
+    content: String,
+    account: MastodonAccount,
+}
+
+#[derive(Deserialize)]
+struct MastodonAccount {
+    acct: String,
+    display_name: String,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct BlueskyThreadResponse {
+    thread: BlueskyThread,
+}
+
+#[derive(Deserialize)]
+struct BlueskyThread {
+    /// Absent for blocked or deleted replies
+    post: Option<BlueskyPost>,
+    #[serde(default)]
+    replies: Vec<BlueskyThread>,
+}
+
+#[derive(Deserialize)]
+struct BlueskyPost {
+    uri: String,
+    author: BlueskyAuthor,
+    record: BlueskyRecord,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlueskyAuthor {
+    handle: String,
+    display_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlueskyRecord {
+    text: String,
+    created_at: String,
+}
+
+/// Returns the comments for a post from all of its sources, oldest first,
+/// reading each source from `cache_dir` when the cached copy is fresh and
+/// fetching (then caching) it otherwise.
+/// If fetching fails, stale cached comments are used rather than failing the build.
+pub(crate) fn comments_for(cache_dir: &Path, key: &str, sources: &[Source]) -> Vec<Comment> {
+    let mut comments: Vec<Comment> = sources
+        .iter()
+        .flat_map(|source| comments_from(cache_dir, key, source))
+        .collect();
+    comments.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+    comments
+}
+
+fn comments_from(cache_dir: &Path, key: &str, source: &Source) -> Vec<Comment> {
+    let cache_path = cache_dir.join(format!("{}.{}.json", key, source.name().to_lowercase()));
+
+    if let Some(comments) = read_cache(&cache_path, true) {
+        return comments;
+    }
+
+    match source.fetch().and_then(|comments| {
+        write_cache(&cache_path, &comments)?;
+        Ok(comments)
+    }) {
+        Ok(comments) => comments,
+        Err(e) => {
+            eprintln!(
+                "Could not fetch {} replies for {}: {:#}",
+                source.name(),
+                key,
+                e
+            );
+            read_cache(&cache_path, false).unwrap_or_default()
+        }
+    }
+}
+
+fn read_cache(cache_path: &Path, require_fresh: bool) -> Option<Vec<Comment>> {
+    if require_fresh {
+        let modified = std::fs::metadata(cache_path).ok()?.modified().ok()?;
+        let age = SystemTime::now().duration_since(modified).ok()?;
+        if age > CACHE_TTL {
+            return None;
+        }
+    }
+
+    let contents = std::fs::read_to_string(cache_path).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+fn write_cache(cache_path: &Path, comments: &[Comment]) -> Result<()> {
+    if let Some(parent) = cache_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Could not create comment cache dir {:?}", parent))?;
+    }
+    std::fs::write(cache_path, serde_json::to_string(comments)?)
+        .with_context(|| format!("Could not write comment cache {:?}", cache_path))
+}
+
+/// A moderated comment kept as a markdown file in the site's
+/// `comments/<post slug>/` directory.
+#[derive(Debug)]
+pub(crate) struct FileComment {
+    /// The file's name without `.md`, which the comment's anchor is made from
+    pub(crate) id: String,
+    pub(crate) author: String,
+    pub(crate) author_url: Option<String>,
+    pub(crate) date: chrono::NaiveDate,
+    pub(crate) body: Markup,
+}
+
+/// The comments in `dir`, oldest first, or none if there's no such directory.
+pub(crate) fn from_files(dir: &Path) -> Result<Vec<FileComment>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut comments = vec![];
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Could not read {:?}", dir))? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "md") {
+            continue;
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read comment {:?}", path))?;
+        let (front_matter, body) =
+            crate::front_matter::parse::<crate::front_matter::CommentFrontMatter>(&content)
+                .with_context(|| format!("Could not parse comment {:?}", path))?;
+        comments.push(FileComment {
+            id: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            author: front_matter.author,
+            author_url: front_matter.url,
+            date: front_matter.date,
+            body: markdown_to_html(body),
+        });
+    }
+    comments.sort_by(|a, b| (a.date, &a.id).cmp(&(b.date, &b.id)));
+    Ok(comments)
+}
+
+/// Renders a comment's markdown with any HTML in it shown as text, so a
+/// comment can't add scripts or styles to the page even if moderation misses it.
+fn markdown_to_html(markdown: &str) -> Markup {
+    let events = Parser::new(markdown).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        event => event,
+    });
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events);
+    maud::PreEscaped(html)
+}
+
+/// Splits a status URL like `https://mastodon.social/@someone/1234`
+/// into its host and status id.
+fn mastodon_host_and_id(status_url: &str) -> Result<(&str, &str)> {
+    let without_scheme = status_url
+        .strip_prefix("https://")
+        .or_else(|| status_url.strip_prefix("http://"))
+        .ok_or_else(|| anyhow!("Mastodon URL must be http(s): {}", status_url))?;
+
+    let (host, path) = without_scheme
+        .split_once('/')
+        .ok_or_else(|| anyhow!("Mastodon URL has no path: {}", status_url))?;
+
+    let id = path
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .filter(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+        .ok_or_else(|| anyhow!("Mastodon URL does not end in a status id: {}", status_url))?;
+
+    Ok((host, id))
+}
+
+fn fetch_mastodon_replies(status_url: &str) -> Result<Vec<Comment>> {
+    let (host, id) = mastodon_host_and_id(status_url)?;
+    let body = ureq::get(&format!("https://{}/api/v1/statuses/{}/context", host, id))
+        .call()?
+        .into_string()?;
+    parse_mastodon_context(&body)
+}
+
+fn parse_mastodon_context(json: &str) -> Result<Vec<Comment>> {
+    let context: MastodonContext = serde_json::from_str(json)?;
+
+    Ok(context
+        .descendants
+        .into_iter()
+        .map(|status| Comment {
+            author: if status.account.display_name.is_empty() {
+                status.account.acct
+            } else {
+                status.account.display_name
+            },
+            author_url: status.account.url,
+            url: status.url.unwrap_or(status.uri),
+            created_at: status.created_at.chars().take(10).collect(),
+            text: html_to_text(&status.content),
+        })
+        .collect())
+}
+
+/// Turns either a `bsky.app` post URL or an `at://` URI into the
+/// URI's authority (a handle or DID) and record key.
+fn bluesky_at_uri(post: &str) -> Result<(String, String)> {
+    let parts: Vec<&str> = if let Some(rest) = post.strip_prefix("at://") {
+        match rest.split('/').collect::<Vec<_>>()[..] {
+            [authority, "app.bsky.feed.post", rkey] => vec![authority, rkey],
+            _ => vec![],
+        }
+    } else if let Some(rest) = post.strip_prefix("https://bsky.app/profile/") {
+        match rest.trim_end_matches('/').split('/').collect::<Vec<_>>()[..] {
+            [authority, "post", rkey] => vec![authority, rkey],
+            _ => vec![],
+        }
+    } else {
+        vec![]
+    };
+
+    match parts[..] {
+        [authority, rkey] if !authority.is_empty() && !rkey.is_empty() => {
+            Ok((authority.to_string(), rkey.to_string()))
+        }
+        _ => Err(anyhow!("Not a Bluesky post URL or at:// URI: {}", post)),
+    }
+}
+
+fn bluesky_web_url(authority: &str, rkey: &str) -> String {
+    format!("https://bsky.app/profile/{}/post/{}", authority, rkey)
+}
+
+fn fetch_bluesky_replies(post: &str) -> Result<Vec<Comment>> {
+    let (authority, rkey) = bluesky_at_uri(post)?;
+    let uri = format!("at://{}/app.bsky.feed.post/{}", authority, rkey);
+    let body = ureq::get("https://public.api.bsky.app/xrpc/app.bsky.feed.getPostThread")
+        .query("uri", &uri)
+        .call()?
+        .into_string()?;
+    parse_bluesky_thread(&body)
+}
+
+fn parse_bluesky_thread(json: &str) -> Result<Vec<Comment>> {
+    fn flatten(thread: BlueskyThread, comments: &mut Vec<Comment>) {
+        for reply in thread.replies {
+            if let Some(post) = &reply.post {
+                let rkey = post.uri.rsplit('/').next().unwrap_or_default();
+                let author_url = format!("https://bsky.app/profile/{}", post.author.handle);
+                comments.push(Comment {
+                    author: post
+                        .author
+                        .display_name
+                        .clone()
+                        .filter(|name| !name.is_empty())
+                        .unwrap_or_else(|| post.author.handle.clone()),
+                    url: bluesky_web_url(&post.author.handle, rkey),
+                    author_url,
+                    created_at: post.record.created_at.chars().take(10).collect(),
+                    text: post.record.text.clone(),
+                });
+            }
+            flatten(reply, comments);
+        }
+    }
+
+    let response: BlueskyThreadResponse = serde_json::from_str(json)?;
+    let mut comments = vec![];
+    flatten(response.thread, &mut comments);
+    Ok(comments)
+}
+
+/// Mastodon hands back status content as HTML; reduce it to text so
+/// nothing remote ends up unescaped in our pages.
+pub(crate) fn html_to_text(html: &str) -> String {
+    let html = html
+        .replace("</p>", "\n\n")
+        .replace("<br>", "\n")
+        .replace("<br/>", "\n")
+        .replace("<br />", "\n");
+
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn reads_comments_from_files() {
+        let dir = crate::test_dir::TestDir::new("comments");
+        std::fs::write(
+            dir.join("2-bob.md"),
+            "---\nauthor: Bob\ndate: 2024-05-08\n---\n\nSecond <script>alert(1)</script>\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("1-ann.md"),
+            "---\nauthor: Ann\nurl: https://ann.example\ndate: 2024-05-07\n---\n\n*First*\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a comment").unwrap();
+
+        let comments = super::from_files(&dir).unwrap();
+        assert_eq!(comments.len(), 2);
+        assert_eq!(comments[0].id, "1-ann");
+        assert_eq!(
+            comments[0].author_url.as_deref(),
+            Some("https://ann.example")
+        );
+        assert_eq!(comments[0].body.0, "<p><em>First</em></p>\n");
+        assert_eq!(
+            comments[1].body.0,
+            "<p>Second &lt;script&gt;alert(1)&lt;/script&gt;</p>\n"
+        );
+
+        assert!(super::from_files(&dir.join("missing")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn embeds_comment_threads() {
+        let mut settings = crate::config::CommentEmbed {
+            provider: crate::config::CommentProvider::Giscus,
+            repo: "a/b".to_string(),
+            mapping: "pathname".to_string(),
+            theme: None,
+            repo_id: Some("R_1".to_string()),
+            category: Some("Comments".to_string()),
+            category_id: Some("DIC_1".to_string()),
+        };
+        assert_eq!(
+            super::embed(&settings).into_string(),
+            "<div class=\"comment-embed\"><script src=\"https://giscus.app/client.js\" data-repo=\"a/b\" data-repo-id=\"R_1\" data-category=\"Comments\" data-category-id=\"DIC_1\" data-mapping=\"pathname\" data-theme=\"preferred_color_scheme\" crossorigin=\"anonymous\" async></script></div>"
+        );
+
+        settings.provider = crate::config::CommentProvider::Utterances;
+        settings.theme = Some("github-dark".to_string());
+        assert_eq!(
+            super::embed(&settings).into_string(),
+            "<div class=\"comment-embed\"><script src=\"https://utteranc.es/client.js\" repo=\"a/b\" issue-term=\"pathname\" theme=\"github-dark\" crossorigin=\"anonymous\" async></script></div>"
+        );
+    }
+
+    #[test]
+    fn splits_mastodon_status_urls() {
+        assert_eq!(
+            super::mastodon_host_and_id("https://mastodon.social/@someone/109876543210").unwrap(),
+            ("mastodon.social", "109876543210")
+        );
+        assert!(super::mastodon_host_and_id("https://mastodon.social/@someone").is_err());
+    }
+
+    #[test]
+    fn reads_bluesky_post_urls_and_uris() {
+        assert_eq!(
+            super::bluesky_at_uri("https://bsky.app/profile/me.bsky.social/post/3kabc").unwrap(),
+            ("me.bsky.social".to_string(), "3kabc".to_string())
+        );
+        assert_eq!(
+            super::bluesky_at_uri("at://did:plc:xyz/app.bsky.feed.post/3kabc").unwrap(),
+            ("did:plc:xyz".to_string(), "3kabc".to_string())
+        );
+        assert!(super::bluesky_at_uri("https://example.com/post/3kabc").is_err());
+    }
+
+    #[test]
+    fn parses_nested_bluesky_replies() {
+        let json = r#"{
+            "thread": {
+                "post": {
+                    "uri": "at://did:plc:me/app.bsky.feed.post/1",
+                    "author": { "handle": "me.bsky.social" },
+                    "record": { "text": "new post", "createdAt": "2024-03-04T00:00:00Z" }
+                },
+                "replies": [{
+                    "post": {
+                        "uri": "at://did:plc:a/app.bsky.feed.post/2",
+                        "author": { "handle": "a.bsky.social", "displayName": "A" },
+                        "record": { "text": "nice", "createdAt": "2024-03-05T00:00:00Z" }
+                    },
+                    "replies": [{
+                        "post": {
+                            "uri": "at://did:plc:me/app.bsky.feed.post/3",
+                            "author": { "handle": "me.bsky.social", "displayName": "" },
+                            "record": { "text": "thanks", "createdAt": "2024-03-06T00:00:00Z" }
+                        }
+                    }]
+                }, {
+                    "$type": "app.bsky.feed.defs#blockedPost"
+                }]
+            }
+        }"#;
+
+        let comments = super::parse_bluesky_thread(json).unwrap();
+
+        assert_eq!(
+            comments
+                .iter()
+                .map(|c| (c.author.as_str(), c.url.as_str(), c.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("A", "https://bsky.app/profile/a.bsky.social/post/2", "nice"),
+                (
+                    "me.bsky.social",
+                    "https://bsky.app/profile/me.bsky.social/post/3",
+                    "thanks"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_mastodon_replies() {
+        let json = r#"{
+            "ancestors": [],
+            "descendants": [{
+                "url": "https://example.social/@a/2",
+                "uri": "https://example.social/users/a/statuses/2",
+                "created_at": "2024-03-05T10:00:00.000Z",
+                "content": "<p><span>@me</span> great &amp; <b>useful</b></p><p>thanks</p>",
+                "account": {
+                    "acct": "a@example.social",
+                    "display_name": "",
+                    "url": "https://example.social/@a"
+                }
+            }]
+        }"#;
+
+        assert_eq!(
+            super::parse_mastodon_context(json).unwrap(),
+            vec![super::Comment {
+                author: "a@example.social".to_string(),
+                author_url: "https://example.social/@a".to_string(),
+                url: "https://example.social/@a/2".to_string(),
+                created_at: "2024-03-05".to_string(),
+                text: "@me great & useful\n\nthanks".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rayon::prelude::*;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Whether `path` is text worth precompressing. Images and fonts are
+/// compressed already.
+pub(crate) fn is_compressible(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == "feed")
+        || path.extension().is_some_and(|ext| {
+            ["html", "css", "js", "json", "xml", "svg"]
+                .iter()
+                .any(|compressible| ext == *compressible)
+        })
+}
+
+/// `path` with `.gz` appended.
+pub(crate) fn gz_path(path: &Path) -> PathBuf {
+    appended(path, ".gz")
+}
+
+/// `path` with `.br` appended.
+pub(crate) fn br_path(path: &Path) -> PathBuf {
+    appended(path, ".br")
+}
+
+fn appended(path: &Path, suffix: &str) -> PathBuf {
+    let mut appended = path.as_os_str().to_os_string();
+    appended.push(suffix);
+    PathBuf::from(appended)
+}
+
+/// Total sizes of a set of files before and after compression.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Sizes {
+    pub(crate) files: usize,
+    pub(crate) original: u64,
+    pub(crate) gzip: u64,
+    pub(crate) brotli: u64,
+}
+
+/// Writes a gzipped and a brotli-compressed copy of each of `files` next to
+/// it, skipping those whose copies are newer than they are.
+pub(crate) fn compress_all(files: &[PathBuf]) -> Result<Sizes> {
+    files
+        .par_iter()
+        .map(|path| {
+            let (gz, br) = (gz_path(path), br_path(path));
+            let modified = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+            if modified(&gz) < modified(path) || modified(&br) < modified(path) {
+                let contents =
+                    std::fs::read(path).with_context(|| format!("Could not read {:?}", path))?;
+                std::fs::write(&gz, gzip(&contents)?)
+                    .with_context(|| format!("Could not write {:?}", gz))?;
+                std::fs::write(&br, brotli(&contents)?)
+                    .with_context(|| format!("Could not write {:?}", br))?;
+            }
+            Ok(Sizes {
+                files: 1,
+                original: path.metadata()?.len(),
+                gzip: gz.metadata()?.len(),
+                brotli: br.metadata()?.len(),
+            })
+        })
+        .try_reduce(Sizes::default, |a, b| {
+            Ok(Sizes {
+                files: a.files + b.files,
+                original: a.original + b.original,
+                gzip: a.gzip + b.gzip,
+                brotli: a.brotli + b.brotli,
+            })
+        })
+}
+
+fn gzip(contents: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(vec![], Compression::best());
+    encoder.write_all(contents)?;
+    Ok(encoder.finish()?)
+}
+
+fn brotli(contents: &[u8]) -> Result<Vec<u8>> {
+    // the best quality, with the window size brotli's own tool defaults to
+    let mut encoder = brotli::CompressorWriter::new(vec![], 4096, 11, 22);
+    encoder.write_all(contents)?;
+    Ok(encoder.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::path::Path;
+
+    #[test]
+    fn compresses_text_files() {
+        assert!(super::is_compressible(Path::new("build/index.html")));
+        assert!(super::is_compressible(Path::new("build/feed")));
+        assert!(!super::is_compressible(Path::new("build/cat.png")));
+
+        let dir = crate::test_dir::TestDir::new("compress");
+        let pages = [dir.join("index.html")];
+        let html = "<p>hello</p>".repeat(100);
+        std::fs::write(&pages[0], &html).unwrap();
+
+        let sizes = super::compress_all(&pages).unwrap();
+        assert_eq!(sizes.files, 1);
+        assert_eq!(sizes.original, html.len() as u64);
+        assert!(sizes.gzip < sizes.original);
+        assert!(sizes.brotli < sizes.original);
+
+        let mut unzipped = String::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(dir.join("index.html.gz")).unwrap())
+            .read_to_string(&mut unzipped)
+            .unwrap();
+        assert_eq!(unzipped, html);
+
+        let mut decompressed = String::new();
+        brotli::Decompressor::new(
+            std::fs::File::open(dir.join("index.html.br")).unwrap(),
+            4096,
+        )
+        .read_to_string(&mut decompressed)
+        .unwrap();
+        assert_eq!(decompressed, html);
+    }
+}
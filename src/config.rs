@@ -0,0 +1,541 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The `base_url` of sites that don't set one, which only works for previews.
+const PREVIEW_BASE_URL: &str = "http://localhost:8080";
+
+/// Site-wide settings, read from `stanley.toml` in the site directory.
+/// Every key is optional.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Shown in the header of every page
+    pub(crate) title: String,
+    /// Used for the description meta tag and as the title of the index page
+    pub(crate) description: String,
+    /// Absolute URL the site is served from, e.g. `https://example.com`
+    pub(crate) base_url: String,
+    /// Who writes the posts, as named in their structured data; defaults to `title`
+    pub(crate) author: Option<String>,
+    /// Where generated files are written, relative to the site directory;
+    /// `--output` takes precedence
+    pub(crate) output: PathBuf,
+    /// URL path of each post, in which `:year`, `:month`, `:day` and `:slug`
+    /// are filled in. One ending in `/` is written to `index.html` in that directory.
+    pub(crate) permalink: String,
+    /// Write posts, pages, and tag, archive year and later index pages as
+    /// `<path>/index.html`, so their URLs end in `/` rather than `.html`
+    pub(crate) pretty_urls: bool,
+    /// Name of a directory under `themes/` whose `templates/` and `static/`
+    /// are used for anything the site doesn't provide itself
+    pub(crate) theme: Option<String>,
+    /// Links in the header of every page
+    pub(crate) nav: Vec<Link>,
+    /// Links in the footer of every page
+    pub(crate) footer: Vec<Link>,
+    pub(crate) feed: Feed,
+    /// How many posts each index page lists; 0 lists them all on one page
+    pub(crate) posts_per_page: usize,
+    /// How many related posts are listed under each post; 0 hides the list
+    pub(crate) related_posts: usize,
+    /// Posts above this Flesch-Kincaid grade level get a warning during the
+    /// build; 0 turns the warnings off
+    pub(crate) readability_threshold: f64,
+    pub(crate) markdown: Markdown,
+    pub(crate) deploy: Deploy,
+    pub(crate) images: Images,
+    /// When set, each post gets a social preview image; needs ImageMagick
+    pub(crate) og_image: Option<OgImage>,
+    pub(crate) webmentions: Webmentions,
+    /// When set, the site is published as a read-only ActivityPub actor
+    pub(crate) activitypub: Option<ActivityPub>,
+    /// When set, a giscus or utterances comment thread is embedded under
+    /// each post that doesn't opt out with `comments: false`
+    pub(crate) comments: Option<CommentEmbed>,
+    pub(crate) hooks: Hooks,
+    /// Address readers can reply to from the bottom of each post
+    pub(crate) reply_email: Option<String>,
+    /// Whether the "Reply via email" link is also appended to feed item content
+    pub(crate) reply_email_in_feed: bool,
+    /// Whether posts marked `draft: true` are built, as with `--drafts`
+    pub(crate) drafts: bool,
+    /// Also write an Apache `.htaccess` redirecting posts' `aliases` to them
+    pub(crate) htaccess: bool,
+    /// Also write Netlify's `_redirects`, for `aliases`, and `_headers`,
+    /// with security headers
+    pub(crate) netlify: bool,
+    /// Write gzipped `.gz` and brotli `.br` copies of every HTML, CSS,
+    /// JavaScript, JSON, XML and SVG file, for servers that can send them as they are
+    pub(crate) precompress: bool,
+    /// Strip comments and collapse whitespace in generated HTML, as with `--minify`
+    pub(crate) minify: bool,
+    /// Also write `links.dot`, the graph of links between posts, for Graphviz
+    pub(crate) link_graph: bool,
+    /// Also publish `growth.html`, charting posts and words written over time
+    pub(crate) growth_page: bool,
+    /// Also publish `lengths.html`, the report of `stanley-rs lengths`
+    pub(crate) lengths_page: bool,
+    /// Set by `serve --watch`: pages load a script that reloads them after each rebuild
+    #[serde(skip)]
+    pub(crate) live_reload: bool,
+    /// Loaded from the site's `templates/` directory, if it has one;
+    /// otherwise the built-in layout is used
+    #[serde(skip)]
+    pub(crate) templates: Option<crate::templates::Templates>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Link {
+    pub(crate) title: String,
+    pub(crate) href: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Feed {
+    /// Defaults to the site description
+    pub(crate) title: Option<String>,
+    /// Defaults to the base URL
+    pub(crate) description: Option<String>,
+    /// How many of the most recent posts the feed includes; 0 includes them all
+    pub(crate) limit: usize,
+    /// Publish only each post's excerpt, or the start of it, instead of its full content
+    pub(crate) summary: bool,
+}
+
+/// Resized copies of large images in posts, for browsers to pick from.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Images {
+    /// Widths in pixels to resize images to; none, the default, turns resizing off
+    pub(crate) widths: Vec<u32>,
+    /// The `sizes` attribute of resized images: how wide they're shown
+    pub(crate) sizes: String,
+    /// Formats to also offer JPEGs and PNGs in, for browsers that support them
+    pub(crate) formats: Vec<ImageFormat>,
+    /// ImageMagick command that does the resizing
+    pub(crate) command: String,
+}
+
+impl Default for Images {
+    fn default() -> Self {
+        Images {
+            widths: vec![],
+            sizes: "100vw".to_string(),
+            formats: vec![],
+            command: "convert".to_string(),
+        }
+    }
+}
+
+/// How posts' social preview images look.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct OgImage {
+    pub(crate) background: String,
+    /// Color of the text
+    pub(crate) color: String,
+    /// A 1200x630 PNG, relative to the site directory, to draw the text over
+    pub(crate) template: Option<PathBuf>,
+}
+
+impl Default for OgImage {
+    fn default() -> Self {
+        OgImage {
+            background: "#1d3557".to_string(),
+            color: "#ffffff".to_string(),
+            template: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ImageFormat {
+    Avif,
+    Webp,
+}
+
+impl ImageFormat {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Avif => "avif",
+            ImageFormat::Webp => "webp",
+        }
+    }
+
+    pub(crate) fn mime_type(self) -> &'static str {
+        match self {
+            ImageFormat::Avif => "image/avif",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// The site's ActivityPub actor, which fediverse servers like Mastodon can
+/// find as `@username@<host of base_url>`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ActivityPub {
+    pub(crate) username: String,
+    /// Inbox URL of a service that accepts follows on the actor's behalf;
+    /// without one, servers can read the posts but not follow them
+    pub(crate) inbox: Option<String>,
+    /// PEM file with the public key of the key the inbox signs with,
+    /// relative to the site directory
+    pub(crate) public_key: Option<PathBuf>,
+}
+
+impl Default for ActivityPub {
+    fn default() -> Self {
+        ActivityPub {
+            username: "blog".to_string(),
+            inbox: None,
+            public_key: None,
+        }
+    }
+}
+
+/// A comment thread kept in a GitHub repository, in its discussions with
+/// giscus or its issues with utterances, embedded under posts.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CommentEmbed {
+    pub(crate) provider: CommentProvider,
+    /// `owner/name` of the repository
+    pub(crate) repo: String,
+    /// How a post is matched to its thread: `pathname`, `url`, `title` or `og:title`
+    #[serde(default = "CommentEmbed::default_mapping")]
+    pub(crate) mapping: String,
+    /// Defaults to following the reader's light or dark preference
+    pub(crate) theme: Option<String>,
+    /// giscus only, like `category` and `category_id`: the ids giscus.app
+    /// gives for the repository and its discussion category
+    pub(crate) repo_id: Option<String>,
+    pub(crate) category: Option<String>,
+    pub(crate) category_id: Option<String>,
+}
+
+impl CommentEmbed {
+    fn default_mapping() -> String {
+        "pathname".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CommentProvider {
+    Giscus,
+    Utterances,
+}
+
+/// Shell commands run around every build, e.g. to compile stylesheets into
+/// `static/` first; a command that fails fails the build.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Hooks {
+    pub(crate) before_build: Vec<String>,
+    pub(crate) after_build: Vec<String>,
+}
+
+/// Webmentions: notifications sent to, and received from, the sites posts link to.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Webmentions {
+    /// After `deploy` publishes the site, notify the sites new and updated posts
+    /// link to that they've been mentioned
+    pub(crate) send: bool,
+    /// Endpoint advertised to other sites for receiving webmentions, e.g.
+    /// `https://webmention.io/example.com/webmention`
+    pub(crate) endpoint: Option<String>,
+    /// Fetch the webmentions webmention.io has received at the start of
+    /// every build, as `webmentions fetch` does
+    pub(crate) fetch: bool,
+}
+
+/// Where `stanley-rs deploy` publishes the build directory.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Deploy {
+    /// rsync/SSH destination, e.g. `user@host:/var/www/site`
+    pub(crate) rsync: Option<String>,
+    pub(crate) s3: Option<S3>,
+    pub(crate) github_pages: Option<GithubPages>,
+}
+
+/// A branch of the site's own git repository to commit the site to, for
+/// GitHub Pages to serve.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct GithubPages {
+    pub(crate) branch: String,
+    /// Remote to push the branch to after committing, e.g. `origin`
+    pub(crate) remote: Option<String>,
+}
+
+impl Default for GithubPages {
+    fn default() -> Self {
+        GithubPages {
+            branch: "gh-pages".to_string(),
+            remote: None,
+        }
+    }
+}
+
+/// An S3-compatible bucket to upload the site to. Credentials come from the
+/// `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` environment variables.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct S3 {
+    pub(crate) bucket: String,
+    /// Defaults to AWS S3 in `region`; set it for R2, MinIO and the like
+    pub(crate) endpoint: Option<String>,
+    #[serde(default = "S3::default_region")]
+    pub(crate) region: String,
+    /// `Cache-Control` sent with every file, unless `cache_control_by_extension`
+    /// has one for its extension
+    #[serde(default = "S3::default_cache_control")]
+    pub(crate) cache_control: String,
+    /// File extension (without the `.`) -> `Cache-Control` for files with it
+    #[serde(default)]
+    pub(crate) cache_control_by_extension: std::collections::BTreeMap<String, String>,
+}
+
+impl S3 {
+    fn default_region() -> String {
+        "us-east-1".to_string()
+    }
+
+    fn default_cache_control() -> String {
+        "public, max-age=300".to_string()
+    }
+
+    pub(crate) fn endpoint(&self) -> String {
+        self.endpoint
+            .clone()
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", self.region))
+    }
+
+    pub(crate) fn cache_control(&self, path: &Path) -> &str {
+        path.extension()
+            .and_then(|ext| self.cache_control_by_extension.get(ext.to_str()?))
+            .unwrap_or(&self.cache_control)
+    }
+}
+
+/// Markdown extensions beyond CommonMark, all on by default except math,
+/// which would turn dollar amounts into equations.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Markdown {
+    pub(crate) tables: bool,
+    pub(crate) footnotes: bool,
+    pub(crate) strikethrough: bool,
+    pub(crate) task_lists: bool,
+    /// `$...$` and `$$...$$` math everywhere; posts and pages can also
+    /// turn it on for themselves with `math: true`
+    pub(crate) math: bool,
+    /// URL path KaTeX's `katex.min.js` and `katex.min.css` are served from
+    pub(crate) katex: String,
+    /// URL path of `mermaid.min.js`, loaded by pages with ```` ```mermaid ```` diagrams
+    pub(crate) mermaid: String,
+}
+
+impl Default for Markdown {
+    fn default() -> Self {
+        Markdown {
+            tables: true,
+            footnotes: true,
+            strikethrough: true,
+            task_lists: true,
+            math: false,
+            katex: "/katex".to_string(),
+            mermaid: "/mermaid.min.js".to_string(),
+        }
+    }
+}
+
+impl Markdown {
+    pub(crate) fn options(&self) -> pulldown_cmark::Options {
+        use pulldown_cmark::Options;
+
+        let mut options = Options::empty();
+        options.set(Options::ENABLE_TABLES, self.tables);
+        options.set(Options::ENABLE_FOOTNOTES, self.footnotes);
+        options.set(Options::ENABLE_STRIKETHROUGH, self.strikethrough);
+        options.set(Options::ENABLE_TASKLISTS, self.task_lists);
+        options.set(Options::ENABLE_MATH, self.math);
+        options
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            title: "stanley".to_string(),
+            description: "A blog built with stanley".to_string(),
+            base_url: PREVIEW_BASE_URL.to_string(),
+            author: None,
+            output: PathBuf::from("build"),
+            permalink: "/:slug.html".to_string(),
+            pretty_urls: false,
+            theme: None,
+            nav: vec![],
+            footer: vec![Link {
+                title: "rss".to_string(),
+                href: "/feed".to_string(),
+            }],
+            feed: Feed::default(),
+            posts_per_page: 20,
+            related_posts: 3,
+            readability_threshold: crate::readability::WARN_ABOVE_GRADE,
+            markdown: Markdown::default(),
+            deploy: Deploy::default(),
+            images: Images::default(),
+            og_image: None,
+            webmentions: Webmentions::default(),
+            activitypub: None,
+            comments: None,
+            hooks: Hooks::default(),
+            reply_email: None,
+            reply_email_in_feed: false,
+            drafts: false,
+            htaccess: false,
+            netlify: false,
+            precompress: false,
+            minify: false,
+            link_graph: false,
+            growth_page: false,
+            lengths_page: false,
+            live_reload: false,
+            templates: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads `stanley.toml` from `site_dir`, falling back to the defaults
+    /// when there is no such file.
+    pub fn load(site_dir: &Path) -> Result<Config> {
+        let path = site_dir.join("stanley.toml");
+
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let contents =
+            std::fs::read_to_string(&path).with_context(|| format!("Could not read {:?}", path))?;
+
+        Config::parse(&contents).with_context(|| format!("Could not parse {:?}", path))
+    }
+
+    fn parse(s: &str) -> Result<Config> {
+        let mut config: Config = toml::from_str(s)?;
+        config.base_url = config.base_url.trim_end_matches('/').to_string();
+        if !config.permalink.starts_with('/')
+            || !config.permalink.contains(":slug")
+            || !(config.permalink.ends_with(".html") || config.permalink.ends_with('/'))
+        {
+            bail!(
+                "permalink {:?} must start with `/`, contain `:slug`, and end with `.html` or `/`",
+                config.permalink
+            );
+        }
+        if let Some(embed) = &config.comments {
+            if embed.provider == CommentProvider::Giscus
+                && (embed.repo_id.is_none()
+                    || embed.category.is_none()
+                    || embed.category_id.is_none())
+            {
+                bail!(
+                    "giscus comments need `repo_id`, `category` and `category_id` under [comments]"
+                );
+            }
+        }
+        Ok(config)
+    }
+
+    /// Whether `base_url` was left unset, so absolute URLs point at localhost.
+    pub(crate) fn has_preview_base_url(&self) -> bool {
+        self.base_url == PREVIEW_BASE_URL
+    }
+
+    /// The directories that `subdir` (`templates` or `static`) is layered
+    /// from, lowest precedence first: the theme's, then the site's own.
+    pub(crate) fn layered_dirs(&self, site_dir: &Path, subdir: &str) -> Result<Vec<PathBuf>> {
+        let mut dirs = vec![];
+        if let Some(theme) = &self.theme {
+            let theme_dir = site_dir.join("themes").join(theme);
+            if !theme_dir.is_dir() {
+                bail!("Theme {:?} not found at {:?}", theme, theme_dir);
+            }
+            dirs.push(theme_dir.join(subdir));
+        }
+        dirs.push(site_dir.join(subdir));
+        Ok(dirs)
+    }
+
+    pub(crate) fn feed_title(&self) -> &str {
+        self.feed.title.as_deref().unwrap_or(&self.description)
+    }
+
+    pub(crate) fn feed_description(&self) -> &str {
+        self.feed.description.as_deref().unwrap_or(&self.base_url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn parses_a_full_config() {
+        let config = Config::parse(
+            r#"
+title = "Clark Kampfe"
+description = "Clark Kampfe - zeroclarkthirty.com"
+base_url = "https://zeroclarkthirty.com/"
+reply_email = "me@example.com"
+reply_email_in_feed = true
+readability_threshold = 0
+
+[[nav]]
+title = "about"
+href = "about.html"
+
+[feed]
+description = "zeroclarkthirty.com"
+
+[markdown]
+footnotes = false
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.title, "Clark Kampfe");
+        assert_eq!(config.base_url, "https://zeroclarkthirty.com");
+        assert_eq!(config.nav[0].href, "about.html");
+        assert_eq!(config.feed_title(), "Clark Kampfe - zeroclarkthirty.com");
+        assert_eq!(config.feed_description(), "zeroclarkthirty.com");
+        assert_eq!(config.footer[0].href, "/feed");
+        assert_eq!(config.readability_threshold, 0.0);
+        assert!(!config.has_preview_base_url());
+        assert!(Config::default().has_preview_base_url());
+        assert_eq!(Config::default().readability_threshold, 14.0);
+        assert!(config.markdown.tables);
+        assert!(!config
+            .markdown
+            .options()
+            .contains(pulldown_cmark::Options::ENABLE_FOOTNOTES));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(Config::parse("titel = \"typo\"").is_err());
+        assert!(Config::parse("permalink = \"/:year/:month/\"").is_err());
+        assert!(Config::parse("[comments]\nprovider = \"giscus\"\nrepo = \"a/b\"").is_err());
+        assert!(Config::parse("[comments]\nprovider = \"utterances\"\nrepo = \"a/b\"").is_ok());
+    }
+}
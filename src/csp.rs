@@ -0,0 +1,144 @@
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+/// The policy every page's `<meta>` tag starts with, allowing only
+/// same-origin resources, until `apply` replaces it with the page's own.
+pub(crate) const PLACEHOLDER: &str = "default-src 'self'";
+
+/// How the layout writes the tag, so `apply` can find it.
+const META: &str = r#"<meta http-equiv="Content-Security-Policy" content=""#;
+
+/// Origins of the comment embeds' scripts, which frame the thread from
+/// the same origin and add a `<style>` element for the frame.
+const EMBED_ORIGINS: [&str; 2] = ["https://giscus.app", "https://utteranc.es"];
+
+/// A Content-Security-Policy for the page `html` that only allows
+/// same-origin resources plus what the page itself uses: the origins of its
+/// external scripts and stylesheets, and its inline `<style>` and `<script>`
+/// elements, matched by hash. Pages with mermaid diagrams or a comment
+/// embed allow any inline style, because mermaid and the embeds add
+/// `<style>` elements of their own, and pages with an embed allow framing it.
+pub(crate) fn policy(html: &str) -> String {
+    let scripts = crate::images::elements(html, "script");
+    let script_origins = origins(
+        scripts
+            .iter()
+            .filter_map(|(tag, _)| crate::images::attribute(tag, "src")),
+    );
+    let embeds: Vec<&str> = script_origins
+        .iter()
+        .copied()
+        .filter(|origin| EMBED_ORIGINS.contains(origin))
+        .collect();
+
+    let mut style_src = sources(&origins(
+        crate::images::tags(html, "link")
+            .into_iter()
+            .filter(|tag| crate::images::attribute(tag, "rel") == Some("stylesheet"))
+            .filter_map(|tag| crate::images::attribute(tag, "href")),
+    ));
+    if html.contains(r#"<pre class="mermaid">"#) || !embeds.is_empty() {
+        style_src.push_str(" 'unsafe-inline'");
+    } else {
+        style_src.push_str(&hash_sources(
+            crate::images::elements(html, "style")
+                .into_iter()
+                .map(|(_, contents)| contents),
+        ));
+    }
+
+    let mut script_src = sources(&script_origins);
+    script_src.push_str(&hash_sources(
+        scripts
+            .iter()
+            .filter(|(tag, _)| crate::images::attribute(tag, "src").is_none())
+            .map(|(_, contents)| *contents),
+    ));
+
+    let mut policy = format!(
+        "default-src 'self'; img-src 'self' https: data:; object-src 'none'; base-uri 'self'; style-src 'self'{}; script-src 'self'{}",
+        style_src, script_src
+    );
+    if !embeds.is_empty() {
+        policy.push_str(&format!("; frame-src 'self'{}", sources(&embeds)));
+    }
+    policy
+}
+
+/// `html` with the policy in its Content-Security-Policy `<meta>` tag, if
+/// it has one, replaced by `policy(html)`.
+pub(crate) fn apply(html: &str) -> String {
+    let Some(start) = html.find(META).map(|start| start + META.len()) else {
+        return html.to_string();
+    };
+    let Some(end) = html[start..].find('"').map(|end| start + end) else {
+        return html.to_string();
+    };
+    format!("{}{}{}", &html[..start], policy(html), &html[end..])
+}
+
+/// The distinct origins of the absolute http(s) `urls`.
+fn origins<'a>(urls: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut origins: Vec<&str> = urls
+        .filter_map(|url| {
+            let (scheme, rest) = url.split_once("://")?;
+            if !matches!(scheme, "http" | "https") {
+                return None;
+            }
+            let host_len = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+            Some(&url[..scheme.len() + 3 + host_len])
+        })
+        .collect();
+    origins.sort_unstable();
+    origins.dedup();
+    origins
+}
+
+fn sources(origins: &[&str]) -> String {
+    origins
+        .iter()
+        .map(|origin| format!(" {}", origin))
+        .collect()
+}
+
+fn hash_sources<'a>(snippets: impl Iterator<Item = &'a str>) -> String {
+    let mut sources: Vec<String> = snippets
+        .map(|snippet| {
+            let digest = Sha256::digest(snippet.as_bytes());
+            format!(
+                " 'sha256-{}'",
+                base64::engine::general_purpose::STANDARD.encode(digest)
+            )
+        })
+        .collect();
+    sources.sort_unstable();
+    sources.dedup();
+    sources.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn allows_what_the_page_uses() {
+        let html = r#"<head><meta http-equiv="Content-Security-Policy" content="default-src 'self'">
+            <style>body{}</style>
+            <link rel="stylesheet" href="/style.css"><link rel="stylesheet" href="https://fonts.example/a.css?b">
+            <script src="https://cdn.example/x.js"></script><script src="/local.js"></script></head>"#;
+        assert_eq!(
+            super::policy(html),
+            "default-src 'self'; img-src 'self' https: data:; object-src 'none'; base-uri 'self'; style-src 'self' https://fonts.example 'sha256-fJgEClQWV1hGkK4qHMO0KotTsVnMYMXTq7/suurGyUo='; script-src 'self' https://cdn.example"
+        );
+        let applied = super::apply(html);
+        assert!(applied.contains(&format!(r#"content="{}">"#, super::policy(html))));
+        // applying it again changes nothing
+        assert_eq!(super::apply(&applied), applied);
+
+        let diagram = r#"<style>body{}</style><pre class="mermaid">a --&gt; b</pre>"#;
+        assert!(super::policy(diagram).contains("style-src 'self' 'unsafe-inline';"));
+
+        let embed = r#"<script src="https://giscus.app/client.js" async></script>"#;
+        assert!(super::policy(embed).ends_with(
+            "style-src 'self' 'unsafe-inline'; script-src 'self' https://giscus.app; frame-src 'self' https://giscus.app"
+        ));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::config;
+use crate::s3::{Bucket, Credentials};
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Key of the object recording what each file in the bucket was uploaded
+/// from: key -> hash of its contents and headers.
+const DEPLOY_MANIFEST: &str = ".stanley-deploy.json";
+
+/// Makes `target`, an rsync/SSH destination like `user@host:/path`, a copy
+/// of `build_dir`: transfers files whose contents differ and deletes remote
+/// files the build no longer has.
+pub(crate) fn rsync(build_dir: &Path, target: &str) -> Result<()> {
+    if !target.contains(':') {
+        bail!(
+            "Deploy target {:?} must be a remote like user@host:/path",
+            target
+        );
+    }
+
+    let status = Command::new("rsync")
+        .args(rsync_args(build_dir, target))
+        .status()
+        .context("Could not run rsync")?;
+    if !status.success() {
+        bail!("rsync to {:?} failed: {}", target, status);
+    }
+    Ok(())
+}
+
+fn rsync_args(build_dir: &Path, target: &str) -> Vec<OsString> {
+    // the trailing slash copies the directory's contents rather than the directory
+    let mut source = build_dir.as_os_str().to_os_string();
+    source.push("/");
+    vec![
+        // build timestamps change on every --force, so compare contents instead
+        "--checksum".into(),
+        "--recursive".into(),
+        "--links".into(),
+        "--times".into(),
+        "--compress".into(),
+        "--delete".into(),
+        "--itemize-changes".into(),
+        "-e".into(),
+        "ssh".into(),
+        source,
+        target.into(),
+    ]
+}
+
+/// Makes the bucket described by `settings` a copy of `build_dir`: uploads
+/// files that are new or changed since the last deploy, with their content
+/// type and configured `Cache-Control`, and deletes objects the build no
+/// longer has.
+pub(crate) fn s3(build_dir: &Path, settings: &config::S3) -> Result<()> {
+    let bucket = Bucket {
+        endpoint: settings.endpoint(),
+        name: settings.bucket.clone(),
+        region: settings.region.clone(),
+        credentials: Credentials::from_env()?,
+    };
+
+    let files: BTreeMap<String, PathBuf> =
+        crate::assets::layered_files(&[build_dir.to_path_buf()])?
+            .into_iter()
+            .map(|(relative, path)| {
+                let key = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                (key, path)
+            })
+            .collect();
+
+    let headers = |path: &Path| {
+        [
+            ("Content-Type", crate::serve::content_type(path)),
+            ("Cache-Control", settings.cache_control(path)),
+        ]
+    };
+    let hashes = files
+        .par_iter()
+        .map(|(key, path)| {
+            let contents =
+                std::fs::read(path).with_context(|| format!("Could not read {:?}", path))?;
+            let [(_, content_type), (_, cache_control)] = headers(path);
+            let hash = crate::manifest::hash(&[
+                &contents,
+                content_type.as_bytes(),
+                cache_control.as_bytes(),
+            ]);
+            Ok((key.clone(), hash))
+        })
+        .collect::<Result<BTreeMap<String, String>>>()?;
+
+    let uploaded: BTreeMap<String, String> = match bucket.get(DEPLOY_MANIFEST)? {
+        Some(json) => serde_json::from_slice(&json).unwrap_or_default(),
+        None => BTreeMap::new(),
+    };
+    let remote: BTreeSet<String> = bucket.list()?.into_iter().collect();
+    let (uploads, deletions) = changes(&hashes, &uploaded, &remote);
+
+    uploads.par_iter().try_for_each(|key| {
+        let path = &files[*key];
+        let contents = std::fs::read(path).with_context(|| format!("Could not read {:?}", path))?;
+        bucket.put(key, &contents, &headers(path))?;
+        println!("Uploaded {}", key);
+        Ok::<(), anyhow::Error>(())
+    })?;
+    deletions.par_iter().try_for_each(|key| {
+        bucket.delete(key)?;
+        println!("Deleted {}", key);
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    bucket.put(
+        DEPLOY_MANIFEST,
+        serde_json::to_string(&hashes)?.as_bytes(),
+        &[
+            ("Content-Type", "application/json"),
+            ("Cache-Control", "no-store"),
+        ],
+    )?;
+    println!(
+        "Deployed to {}: {} uploaded, {} deleted, {} unchanged",
+        settings.bucket,
+        uploads.len(),
+        deletions.len(),
+        hashes.len() - uploads.len()
+    );
+    Ok(())
+}
+
+/// Commits the contents of `build_dir` to `settings.branch` of the git
+/// repository at `site_dir`, with a `.nojekyll` so GitHub Pages serves the
+/// files as they are, and pushes the branch if a remote is configured. The
+/// working tree and the checked out branch aren't touched.
+pub(crate) fn github_pages(
+    site_dir: &Path,
+    build_dir: &Path,
+    settings: &config::GithubPages,
+) -> Result<()> {
+    let nojekyll = build_dir.join(".nojekyll");
+    if !nojekyll.exists() {
+        std::fs::write(&nojekyll, "").with_context(|| format!("Could not write {:?}", nojekyll))?;
+    }
+
+    let build_dir = build_dir
+        .canonicalize()
+        .with_context(|| format!("Could not find {:?}", build_dir))?;
+    // a separate index, so whatever is staged in the site's own isn't disturbed
+    let index = site_dir
+        .canonicalize()
+        .with_context(|| format!("Could not find {:?}", site_dir))?
+        .join(".cache")
+        .join("pages-index");
+    let _ = std::fs::remove_file(&index);
+    let git = |args: &[&str]| git(site_dir, &index, args);
+
+    let work_tree = format!("--work-tree={}", build_dir.display());
+    // ignore rules written for the sources shouldn't drop built files
+    git(&[&work_tree, "add", "--all", "--force", "."])?;
+    let tree = git(&["write-tree"]);
+    let _ = std::fs::remove_file(&index);
+    let tree = tree?;
+
+    let branch = format!("refs/heads/{}", settings.branch);
+    let parent = git(&["rev-parse", "--verify", "--quiet", &branch]).ok();
+    let unchanged = match &parent {
+        Some(parent) => git(&["rev-parse", &format!("{}^{{tree}}", parent)])? == tree,
+        None => false,
+    };
+    if unchanged {
+        println!("{} is already up to date", settings.branch);
+    } else {
+        let mut args = vec!["commit-tree", &tree, "-m", "Publish site"];
+        if let Some(parent) = &parent {
+            args.extend(["-p", parent]);
+        }
+        let commit = git(&args)?;
+        git(&["update-ref", &branch, &commit])?;
+        println!("Committed the site to {} as {}", settings.branch, commit);
+    }
+
+    if let Some(remote) = &settings.remote {
+        git(&["push", remote, &format!("{}:{}", branch, branch)])?;
+        println!("Pushed {} to {}", settings.branch, remote);
+    }
+    Ok(())
+}
+
+/// Runs git in `site_dir` with `index` as its index, returning its trimmed output.
+fn git(site_dir: &Path, index: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(site_dir)
+        .args(args)
+        .env("GIT_INDEX_FILE", index)
+        .output()
+        .context("Could not run git")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The keys of `local` files to upload, because they aren't in the bucket or
+/// have changed since they were uploaded, and the keys in the bucket to
+/// delete, because the build no longer has them.
+fn changes<'a>(
+    local: &'a BTreeMap<String, String>,
+    uploaded: &BTreeMap<String, String>,
+    remote: &'a BTreeSet<String>,
+) -> (Vec<&'a String>, Vec<&'a String>) {
+    let uploads = local
+        .iter()
+        .filter(|(key, hash)| !remote.contains(*key) || uploaded.get(*key) != Some(hash))
+        .map(|(key, _)| key)
+        .collect();
+    let deletions = remote
+        .iter()
+        .filter(|key| *key != DEPLOY_MANIFEST && !local.contains_key(*key))
+        .collect();
+    (uploads, deletions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::path::Path;
+
+    #[test]
+    fn syncs_the_contents_of_the_build_dir() {
+        let args = super::rsync_args(Path::new("site/build"), "me@example.com:/var/www");
+        assert_eq!(args[args.len() - 2], "site/build/");
+        assert_eq!(args[args.len() - 1], "me@example.com:/var/www");
+        assert!(args.iter().any(|arg| arg == "--delete"));
+
+        assert!(super::rsync(Path::new("build"), "/var/www").is_err());
+    }
+
+    #[test]
+    fn uploads_changed_files_and_deletes_removed_ones() {
+        let map = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, hash)| (key.to_string(), hash.to_string()))
+                .collect()
+        };
+        let local = map(&[("a.html", "1"), ("b.html", "2"), ("c.html", "3")]);
+        let uploaded = map(&[("a.html", "1"), ("b.html", "old"), ("c.html", "3")]);
+        let remote: BTreeSet<String> = ["a.html", "b.html", "gone.html", super::DEPLOY_MANIFEST]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let (uploads, deletions) = super::changes(&local, &uploaded, &remote);
+        assert_eq!(uploads, ["b.html", "c.html"]);
+        assert_eq!(deletions, ["gone.html"]);
+    }
+
+    #[test]
+    fn commits_the_build_to_a_branch() {
+        let site_dir = crate::test_dir::TestDir::new("pages");
+        let build_dir = site_dir.join("build");
+        std::fs::create_dir_all(&build_dir).unwrap();
+        std::fs::create_dir_all(site_dir.join(".cache")).unwrap();
+        std::fs::write(site_dir.join(".gitignore"), "build/\n*.html\n").unwrap();
+        std::fs::write(build_dir.join("index.html"), "hello").unwrap();
+        let git = |args: &[&str]| {
+            super::git(&site_dir, &site_dir.join(".git").join("index"), args).unwrap()
+        };
+        git(&["init", "--quiet"]);
+        git(&["config", "user.name", "Stanley"]);
+        git(&["config", "user.email", "stanley@example.com"]);
+
+        let settings = crate::config::GithubPages::default();
+        super::github_pages(&site_dir, &build_dir, &settings).unwrap();
+        assert_eq!(git(&["show", "gh-pages:index.html"]), "hello");
+        assert_eq!(
+            git(&["ls-tree", "--name-only", "gh-pages"]),
+            ".nojekyll\nindex.html"
+        );
+
+        // nothing changed, so nothing to commit
+        super::github_pages(&site_dir, &build_dir, &settings).unwrap();
+        assert_eq!(git(&["rev-list", "--count", "gh-pages"]), "1");
+        assert_eq!(git(&["status", "--porcelain"]), "?? .gitignore");
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use maud::{html, Markup, PreEscaped};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::num::NonZeroU32;
+
+const PBKDF2_ITERATIONS: u32 = 200_000;
+const SALT_LEN: usize = 16;
+
+/// Decrypts the `#locked` element in the browser with WebCrypto, using the
+/// same PBKDF2 and AES-GCM parameters as `encrypt`.
+pub(crate) const DECRYPT_SCRIPT: &str = r#"document.getElementById("unlock").addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const locked = document.getElementById("locked");
+  const bytes = (b64) => Uint8Array.from(atob(b64), (c) => c.charCodeAt(0));
+  const password = new TextEncoder().encode(event.target.elements.password.value);
+  const material = await crypto.subtle.importKey("raw", password, "PBKDF2", false, ["deriveKey"]);
+  const key = await crypto.subtle.deriveKey(
+    { name: "PBKDF2", salt: bytes(locked.dataset.salt), iterations: Number(locked.dataset.iterations), hash: "SHA-256" },
+    material,
+    { name: "AES-GCM", length: 256 },
+    false,
+    ["decrypt"],
+  );
+  try {
+    const plain = await crypto.subtle.decrypt({ name: "AES-GCM", iv: bytes(locked.dataset.iv) }, key, bytes(locked.dataset.ciphertext));
+    event.target.remove();
+    locked.outerHTML = new TextDecoder().decode(plain);
+  } catch {
+    document.getElementById("unlock-error").hidden = false;
+  }
+});"#;
+
+/// Rendered post HTML encrypted with a key derived from a passphrase.
+/// All fields are base64.
+pub(crate) struct Encrypted {
+    salt: String,
+    iv: String,
+    /// AES-GCM ciphertext with the tag appended, as WebCrypto expects
+    ciphertext: String,
+}
+
+fn derive_key(password: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        salt,
+        password.as_bytes(),
+        &mut key,
+    );
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key).expect("key is 32 bytes"))
+}
+
+pub(crate) fn encrypt(html: &str, password: &str) -> Result<Encrypted> {
+    let rng = SystemRandom::new();
+    let mut salt = [0; SALT_LEN];
+    let mut iv = [0; NONCE_LEN];
+    rng.fill(&mut salt)
+        .map_err(|_| anyhow!("Could not generate salt"))?;
+    rng.fill(&mut iv)
+        .map_err(|_| anyhow!("Could not generate IV"))?;
+
+    let mut ciphertext = html.as_bytes().to_vec();
+    derive_key(password, &salt)
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(iv),
+            Aad::empty(),
+            &mut ciphertext,
+        )
+        .map_err(|_| anyhow!("Could not encrypt post"))?;
+
+    let b64 = base64::engine::general_purpose::STANDARD;
+    Ok(Encrypted {
+        salt: b64.encode(salt),
+        iv: b64.encode(iv),
+        ciphertext: b64.encode(ciphertext),
+    })
+}
+
+/// The passphrase prompt shown in place of an encrypted post's body.
+pub(crate) fn locked_body(encrypted: &Encrypted) -> Markup {
+    html! {
+        form id="unlock" {
+            p { "This post is password protected." }
+            input type="password" name="password" aria-label="Password" autocomplete="current-password";
+            " "
+            button type="submit" { "Unlock" }
+            p id="unlock-error" hidden { "Wrong password." }
+        }
+        div id="locked"
+            data-salt=(encrypted.salt)
+            data-iv=(encrypted.iv)
+            data-iterations=(PBKDF2_ITERATIONS)
+            data-ciphertext=(encrypted.ciphertext) {}
+        script { (PreEscaped(DECRYPT_SCRIPT)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use ring::aead::{Aad, Nonce};
+
+    #[test]
+    fn round_trips_with_the_right_password() {
+        let encrypted = super::encrypt("<p>secret</p>", "hunter2").unwrap();
+
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let salt = b64.decode(&encrypted.salt).unwrap();
+        let iv: [u8; 12] = b64.decode(&encrypted.iv).unwrap().try_into().unwrap();
+        let ciphertext = b64.decode(&encrypted.ciphertext).unwrap();
+
+        let mut wrong = ciphertext.clone();
+        assert!(super::derive_key("nope", &salt)
+            .open_in_place(Nonce::assume_unique_for_key(iv), Aad::empty(), &mut wrong)
+            .is_err());
+
+        let mut right = ciphertext;
+        let plain = super::derive_key("hunter2", &salt)
+            .open_in_place(Nonce::assume_unique_for_key(iv), Aad::empty(), &mut right)
+            .unwrap();
+        assert_eq!(plain, b"<p>secret</p>");
+    }
+}
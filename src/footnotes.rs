@@ -0,0 +1,123 @@
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use std::collections::HashMap;
+
+/// Numbers footnotes in the order they're first referenced and moves their
+/// definitions from wherever they were written into a `<section class="footnotes">`
+/// at the end, each with a `↩` link back to its first reference.
+pub(crate) fn footnotes<'a>(events: impl Iterator<Item = Event<'a>>) -> Vec<Event<'a>> {
+    let mut out = vec![];
+    // label -> (number, times referenced so far)
+    let mut numbers: HashMap<CowStr<'a>, (usize, usize)> = HashMap::new();
+    let mut definitions: HashMap<CowStr<'a>, Vec<Event<'a>>> = HashMap::new();
+    let mut defined_order = vec![];
+    let mut in_definition: Option<(CowStr<'a>, Vec<Event<'a>>)> = None;
+
+    for event in events {
+        match (&mut in_definition, event) {
+            (None, Event::Start(Tag::FootnoteDefinition(label))) => {
+                in_definition = Some((label, vec![]))
+            }
+            (Some(_), Event::End(TagEnd::FootnoteDefinition)) => {
+                let (label, inner) = in_definition.take().expect("inside a definition");
+                defined_order.push(label.clone());
+                definitions.insert(label, inner);
+            }
+            (Some((_, inner)), event) => inner.push(event),
+            (None, Event::FootnoteReference(label)) => {
+                let next = numbers.len() + 1;
+                let (number, references) = numbers.entry(label).or_insert((next, 0));
+                *references += 1;
+                // only the first reference gets the id that the backlink points at
+                let id = match *references {
+                    1 => format!("fnref-{}", number),
+                    n => format!("fnref-{}-{}", number, n),
+                };
+                out.push(Event::InlineHtml(
+                    format!(
+                        "<sup class=\"footnote-reference\" id=\"{}\"><a href=\"#fn-{}\">{}</a></sup>",
+                        id, number, number
+                    )
+                    .into(),
+                ));
+            }
+            (None, event) => out.push(event),
+        }
+    }
+
+    if definitions.is_empty() {
+        return out;
+    }
+
+    // referenced footnotes in reference order, then any that never were
+    let mut order: Vec<(CowStr<'a>, Option<usize>)> = numbers
+        .iter()
+        .map(|(label, (number, _))| (label.clone(), Some(*number)))
+        .collect();
+    order.sort_by_key(|(_, number)| *number);
+    order.extend(
+        defined_order
+            .into_iter()
+            .filter(|label| !numbers.contains_key(label))
+            .map(|label| (label, None)),
+    );
+
+    out.push(Event::Html(
+        "<section class=\"footnotes\" role=\"doc-endnotes\">\n<ol>\n".into(),
+    ));
+    for (label, number) in order {
+        let Some(mut inner) = definitions.remove(&label) else {
+            continue;
+        };
+        let Some(number) = number else {
+            out.push(Event::Html("<li>\n".into()));
+            out.extend(inner);
+            out.push(Event::Html("</li>\n".into()));
+            continue;
+        };
+
+        let backlink = Event::InlineHtml(
+            format!(
+                " <a class=\"footnote-backref\" href=\"#fnref-{}\" aria-label=\"Back to reference {}\">↩</a>",
+                number, number
+            )
+            .into(),
+        );
+        // inside the last paragraph, so it sits at the end of the text rather than below it
+        match inner.last() {
+            Some(Event::End(TagEnd::Paragraph)) => inner.insert(inner.len() - 1, backlink),
+            _ => inner.push(backlink),
+        }
+
+        out.push(Event::Html(format!("<li id=\"fn-{}\">\n", number).into()));
+        out.extend(inner);
+        out.push(Event::Html("</li>\n".into()));
+    }
+    out.push(Event::Html("</ol>\n</section>\n".into()));
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::{html, Options, Parser};
+
+    #[test]
+    fn collects_definitions_with_backlinks() {
+        let markdown = "One[^b], two[^a], one again[^b].\n\n[^a]: First defined.\n[^b]: Second defined.\n[^c]: Never referenced.\n";
+        let events = super::footnotes(Parser::new_ext(markdown, Options::ENABLE_FOOTNOTES));
+        let mut body = String::new();
+        html::push_html(&mut body, events.into_iter());
+
+        assert_eq!(
+            body,
+            "<p>One<sup class=\"footnote-reference\" id=\"fnref-1\"><a href=\"#fn-1\">1</a></sup>, \
+             two<sup class=\"footnote-reference\" id=\"fnref-2\"><a href=\"#fn-2\">2</a></sup>, \
+             one again<sup class=\"footnote-reference\" id=\"fnref-1-2\"><a href=\"#fn-1\">1</a></sup>.</p>\n\
+             <section class=\"footnotes\" role=\"doc-endnotes\">\n<ol>\n\
+             <li id=\"fn-1\">\n<p>Second defined. <a class=\"footnote-backref\" href=\"#fnref-1\" aria-label=\"Back to reference 1\">↩</a></p>\n</li>\n\
+             <li id=\"fn-2\">\n<p>First defined. <a class=\"footnote-backref\" href=\"#fnref-2\" aria-label=\"Back to reference 2\">↩</a></p>\n</li>\n\
+             <li>\n<p>Never referenced.</p>\n</li>\n\
+             </ol>\n</section>\n"
+        );
+    }
+}
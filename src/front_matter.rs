@@ -0,0 +1,144 @@
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+
+/// The YAML block at the top of a post. Keys may appear in any order,
+/// and keys stanley doesn't know about are ignored.
+#[derive(Debug, Deserialize)]
+pub(crate) struct PostFrontMatter {
+    pub(crate) title: String,
+    pub(crate) created: chrono::NaiveDate,
+    /// When the post was last meaningfully edited
+    pub(crate) updated: Option<chrono::NaiveDate>,
+    /// URL of the toot announcing this post, whose replies are shown as comments
+    pub(crate) mastodon: Option<String>,
+    /// URL or `at://` URI of the Bluesky post announcing this post, likewise
+    pub(crate) bluesky: Option<String>,
+    /// When set, the body is encrypted with this passphrase
+    pub(crate) password: Option<String>,
+    /// Markdown summary for listings and the feed; defaults to the body
+    /// up to a `<!-- more -->` line
+    pub(crate) excerpt: Option<String>,
+    /// Name of the output file; defaults to the title, slugified
+    pub(crate) slug: Option<String>,
+    /// Drafts are left out of the build unless drafts are enabled
+    #[serde(default)]
+    pub(crate) draft: bool,
+    /// Whether to show a table of contents at the top of the post
+    #[serde(default)]
+    pub(crate) toc: bool,
+    /// Whether `$...$` is math in this post, as with the site-wide setting
+    #[serde(default)]
+    pub(crate) math: bool,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub(crate) tags: Vec<String>,
+    /// Old URL paths of the post, which redirect to it
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub(crate) aliases: Vec<String>,
+    /// Stylesheets from the post's asset directory to link on its page only
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub(crate) css: Vec<String>,
+    /// Scripts from the post's asset directory to load on its page only
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub(crate) js: Vec<String>,
+    /// Whether the site's comment embed is shown under the post
+    #[serde(default = "PostFrontMatter::default_comments")]
+    pub(crate) comments: bool,
+}
+
+impl PostFrontMatter {
+    fn default_comments() -> bool {
+        true
+    }
+}
+
+/// The YAML block at the top of a page.
+#[derive(Debug, Deserialize)]
+pub(crate) struct PageFrontMatter {
+    pub(crate) title: String,
+    /// Whether `$...$` is math in this page, as with the site-wide setting
+    #[serde(default)]
+    pub(crate) math: bool,
+}
+
+/// The YAML block at the top of a comment in `comments/<post slug>/`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct CommentFrontMatter {
+    pub(crate) author: String,
+    /// The commenter's website
+    pub(crate) url: Option<String>,
+    pub(crate) date: chrono::NaiveDate,
+}
+
+/// Splits a document into its parsed front matter and the markdown body
+/// following it. The front matter is delimited by `---` lines.
+pub(crate) fn parse<T: DeserializeOwned>(s: &str) -> Result<(T, &str)> {
+    let (front_matter, body) = split(s)?;
+    let parsed = serde_yaml::from_str(front_matter).context("Invalid front matter")?;
+    Ok((parsed, body))
+}
+
+/// Splits a document into its unparsed front matter and its body.
+pub(crate) fn split(s: &str) -> Result<(&str, &str)> {
+    let rest = s
+        .strip_prefix("---\n")
+        .or_else(|| s.strip_prefix("---\r\n"))
+        .ok_or_else(|| anyhow!("Front matter must start with a `---` line"))?;
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return Ok((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+
+    Err(anyhow!("Front matter is missing its closing `---` line"))
+}
+
+/// Accepts either a single string or a list of strings.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(one) => vec![one],
+        OneOrMany::Many(many) => many,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PageFrontMatter, PostFrontMatter};
+
+    #[test]
+    fn accepts_keys_in_any_order() {
+        let (front_matter, body) = super::parse::<PostFrontMatter>(
+            "---\ncreated: 2029-12-18\nunknown: ignored\ntitle: \"a: title\"\ncss: [a.css, b.css]\njs: c.js\n---\nbody\n",
+        )
+        .unwrap();
+
+        assert_eq!(front_matter.title, "a: title");
+        assert_eq!(
+            front_matter.created,
+            chrono::NaiveDate::from_ymd_opt(2029, 12, 18).unwrap()
+        );
+        assert_eq!(front_matter.css, vec!["a.css", "b.css"]);
+        assert_eq!(front_matter.js, vec!["c.js"]);
+        assert_eq!(body, "body\n");
+    }
+
+    #[test]
+    fn explains_malformed_front_matter() {
+        let missing_title =
+            super::parse::<PageFrontMatter>("---\ntitel: x\n---\nbody").unwrap_err();
+        assert!(format!("{:#}", missing_title).contains("missing field `title`"));
+
+        let unclosed = super::parse::<PageFrontMatter>("---\ntitle: x\nbody").unwrap_err();
+        assert!(unclosed.to_string().contains("closing"));
+    }
+}
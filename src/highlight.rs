@@ -0,0 +1,134 @@
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use std::sync::OnceLock;
+use syntect::highlighting::ThemeSet;
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+/// Highlighted code is marked up with classes rather than inline styles,
+/// which the Content-Security-Policy would block. They're prefixed so they
+/// can't collide with the site's own CSS.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// The colors of highlighted code, from syntect's bundled themes.
+const THEME: &str = "InspiredGitHub";
+
+/// Where the theme's stylesheet is written, relative to the build directory.
+pub(crate) const STYLESHEET: &str = "syntax.css";
+
+/// Code blocks in this language are diagrams for mermaid to draw in the browser.
+const MERMAID: &str = "mermaid";
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// The stylesheet that colors highlighted code.
+pub(crate) fn stylesheet() -> String {
+    let themes = ThemeSet::load_defaults();
+    css_for_theme_with_class_style(&themes.themes[THEME], CLASS_STYLE)
+        .expect("bundled theme converts to CSS")
+}
+
+/// Replaces every fenced code block whose language syntect knows with
+/// highlighted HTML. Other code blocks pass through untouched.
+pub(crate) fn highlight_code_blocks<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+) -> impl Iterator<Item = Event<'a>> {
+    let mut in_block: Option<(CowStr<'a>, String)> = None;
+
+    events.filter_map(move |event| match (&mut in_block, event) {
+        (None, Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))) => {
+            in_block = Some((info, String::new()));
+            None
+        }
+        (Some((_, code)), Event::Text(text)) => {
+            code.push_str(&text);
+            None
+        }
+        (Some(_), Event::End(TagEnd::CodeBlock)) => {
+            let (info, code) = in_block.take().expect("inside a code block");
+            Some(Event::Html(highlight(&info, &code).into()))
+        }
+        (_, event) => Some(event),
+    })
+}
+
+/// Whether `markdown_str` has a mermaid diagram in it, so mermaid is only loaded where it's needed.
+pub(crate) fn has_diagrams(markdown_str: &str, options: Options) -> bool {
+    Parser::new_ext(markdown_str, options).any(|event| {
+        matches!(event, Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) if lang(&info) == MERMAID)
+    })
+}
+
+/// The first word of a fence's info string.
+fn lang(info: &str) -> &str {
+    info.split([' ', ',']).next().unwrap_or_default()
+}
+
+/// `code` as a `<pre>` block, highlighted if the first word of the fence's
+/// info string names a known language. Mermaid diagrams are left as
+/// source in a `<pre class="mermaid">`, which mermaid replaces with the drawing.
+fn highlight(info: &str, code: &str) -> String {
+    let lang = lang(info);
+    if lang == MERMAID {
+        return format!("<pre class=\"mermaid\">{}</pre>\n", escape(code));
+    }
+    let syntax_set = syntax_set();
+
+    let highlighted = syntax_set.find_syntax_by_token(lang).and_then(|syntax| {
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, syntax_set, CLASS_STYLE);
+        for line in LinesWithEndings::from(code) {
+            generator
+                .parse_html_for_line_which_includes_newline(line)
+                .ok()?;
+        }
+        Some(generator.finalize())
+    });
+
+    match highlighted {
+        Some(html) => format!(
+            "<pre class=\"hl-code\"><code class=\"language-{}\">{}</code></pre>\n",
+            escape(lang),
+            html
+        ),
+        None if lang.is_empty() => format!("<pre><code>{}</code></pre>\n", escape(code)),
+        None => format!(
+            "<pre><code class=\"language-{}\">{}</code></pre>\n",
+            escape(lang),
+            escape(code)
+        ),
+    }
+}
+
+fn escape(s: &str) -> String {
+    maud::html! { (s) }.into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn highlights_known_languages_only() {
+        let rust = super::highlight("rust,ignore", "fn main() {}\n");
+        assert!(rust.starts_with("<pre class=\"hl-code\"><code class=\"language-rust\">"));
+        assert!(rust.contains("<span class=\"hl-storage hl-type hl-function hl-rust\">fn</span>"));
+
+        assert_eq!(
+            super::highlight("not-a-language", "<b>\n"),
+            "<pre><code class=\"language-not-a-language\">&lt;b&gt;\n</code></pre>\n"
+        );
+
+        assert_eq!(
+            super::highlight("mermaid", "a --> b\n"),
+            "<pre class=\"mermaid\">a --&gt; b\n</pre>\n"
+        );
+        let options = pulldown_cmark::Options::empty();
+        assert!(super::has_diagrams("```mermaid\na --> b\n```\n", options));
+        assert!(!super::has_diagrams(
+            "```rust\nfn main() {}\n```\n",
+            options
+        ));
+    }
+}
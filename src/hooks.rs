@@ -0,0 +1,43 @@
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+/// Runs each of `commands` with `sh -c` in `site_dir`, in order, stopping at
+/// the first that fails. They can find the output directory in
+/// `STANLEY_BUILD_DIR`.
+pub(crate) fn run(commands: &[String], site_dir: &Path, build_dir: &Path) -> Result<()> {
+    for command in commands {
+        println!("Running {:?}", command);
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(site_dir)
+            .env("STANLEY_BUILD_DIR", build_dir)
+            .status()
+            .with_context(|| format!("Could not run hook {:?}", command))?;
+        if !status.success() {
+            bail!("Hook {:?} failed: {}", command, status);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    #[test]
+    fn runs_commands_until_one_fails() {
+        let dir = crate::test_dir::TestDir::new("hooks");
+        let commands = [
+            "echo \"$STANLEY_BUILD_DIR\" > built".to_string(),
+            "exit 3".to_string(),
+            "touch never".to_string(),
+        ];
+
+        let error = super::run(&commands, &dir, Path::new("out")).unwrap_err();
+        assert!(error.to_string().contains("\"exit 3\" failed"));
+        assert_eq!(std::fs::read_to_string(dir.join("built")).unwrap(), "out\n");
+        assert!(!dir.join("never").exists());
+    }
+}
@@ -0,0 +1,426 @@
+use crate::config;
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A resized or converted copy of an image.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Variant {
+    pub(crate) source: PathBuf,
+    /// ImageMagick picks the format from its extension
+    pub(crate) path: PathBuf,
+    /// `None` keeps the source's size
+    pub(crate) width: Option<u32>,
+}
+
+/// Rewrites each `<img>` in `html` whose `src` is a local JPEG, PNG, GIF or
+/// WebP to offer browsers smaller or better compressed copies of it:
+/// a `srcset` of copies at each configured width smaller than the image,
+/// with a `sizes`, and for JPEGs and PNGs, a `<picture>` with a `<source>`
+/// for each configured format, the `<img>` being the fallback.
+/// `local_file` maps a `src` to the file it's served from, if it's local.
+/// Returns the new HTML and the copies it refers to, which `convert` writes.
+pub(crate) fn responsive(
+    html: &str,
+    settings: &config::Images,
+    local_file: impl Fn(&str) -> Option<PathBuf>,
+) -> (String, Vec<Variant>) {
+    let mut variants = vec![];
+    let html = rewrite_img_tags(html, |tag| {
+        if attribute(tag, "srcset").is_some() {
+            return tag.to_string();
+        }
+        let Some((src, source)) =
+            attribute(tag, "src").and_then(|src| Some((src, local_file(src)?)))
+        else {
+            return tag.to_string();
+        };
+        let Some((width, _)) = dimensions(&source) else {
+            return tag.to_string();
+        };
+        let widths: Vec<u32> = settings
+            .widths
+            .iter()
+            .copied()
+            .filter(|w| *w < width)
+            .collect();
+        let convertible = source.extension().is_some_and(|ext| {
+            ["jpg", "jpeg", "png"]
+                .iter()
+                .any(|convertible| ext.eq_ignore_ascii_case(convertible))
+        });
+        let formats: &[config::ImageFormat] = if convertible { &settings.formats } else { &[] };
+
+        // the copies of the image in one format, as a `srcset`
+        let mut srcset = |extension: Option<&str>| {
+            let mut candidates = vec![];
+            for w in widths.iter().map(|w| Some(*w)).chain([None]) {
+                if w.is_none() && extension.is_none() {
+                    // the original itself
+                    candidates.push(format!("{} {}w", src, width));
+                    continue;
+                }
+                variants.push(Variant {
+                    source: source.clone(),
+                    path: PathBuf::from(variant_name(&source.to_string_lossy(), w, extension)),
+                    width: w,
+                });
+                candidates.push(format!(
+                    "{} {}w",
+                    variant_name(src, w, extension),
+                    w.unwrap_or(width)
+                ));
+            }
+            candidates.join(", ")
+        };
+
+        let sizes = (!widths.is_empty()).then(|| ("sizes", settings.sizes.clone()));
+        let img = match &sizes {
+            Some(sizes) => with_attributes(tag, &[("srcset", srcset(None)), sizes.clone()]),
+            None => tag.to_string(),
+        };
+        if formats.is_empty() {
+            return img;
+        }
+
+        let mut picture = "<picture>".to_string();
+        for format in formats {
+            let attributes: Vec<(&str, String)> = [
+                ("type", format.mime_type().to_string()),
+                ("srcset", srcset(Some(format.extension()))),
+            ]
+            .into_iter()
+            .chain(sizes.clone())
+            .collect();
+            picture.push_str(&with_attributes("<source>", &attributes));
+        }
+        picture.push_str(&img);
+        picture.push_str("</picture>");
+        picture
+    });
+    (html, variants)
+}
+
+/// Adds `loading="lazy"` and `decoding="async"` to each `<img>` in `html`,
+/// so images below the fold aren't fetched until they're scrolled to, and
+/// the `width` and `height` of local images, read from their files, so the
+/// browser can leave room for them before they load. Attributes a tag
+/// already has are kept.
+pub(crate) fn lazy_load(html: &str, local_file: impl Fn(&str) -> Option<PathBuf>) -> String {
+    rewrite_img_tags(html, |tag| {
+        let mut attributes = vec![
+            ("loading", "lazy".to_string()),
+            ("decoding", "async".to_string()),
+        ];
+        let sized = attribute(tag, "width").is_some() || attribute(tag, "height").is_some();
+        if let Some((width, height)) = attribute(tag, "src")
+            .filter(|_| !sized)
+            .and_then(&local_file)
+            .and_then(|file| dimensions(&file))
+        {
+            attributes.push(("width", width.to_string()));
+            attributes.push(("height", height.to_string()));
+        }
+        with_attributes(tag, &attributes)
+    })
+}
+
+/// Writes each of `variants` with ImageMagick's `command`, skipping those
+/// already newer than their source.
+pub(crate) fn convert(command: &str, variants: &BTreeSet<Variant>) -> Result<()> {
+    variants.par_iter().try_for_each(|variant| {
+        let modified = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+        if modified(&variant.path) >= modified(&variant.source) {
+            return Ok(());
+        }
+
+        let mut convert = Command::new(command);
+        convert.arg(&variant.source);
+        if let Some(width) = variant.width {
+            convert.args(["-resize", &format!("{}x", width)]);
+        }
+        let output = convert
+            .arg("-strip")
+            .arg(&variant.path)
+            .output()
+            .with_context(|| format!("Could not run {:?} to convert images", command))?;
+        if !output.status.success() {
+            bail!(
+                "Could not convert {:?} to {:?}: {}",
+                variant.source,
+                variant.path,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(())
+    })
+}
+
+/// `name` with `-<width>w` inserted before its extension, if there's a width,
+/// and that extension replaced, if there's a new one: with both, `cat.jpg`
+/// becomes `cat-480w.webp`.
+fn variant_name(name: &str, width: Option<u32>, new_extension: Option<&str>) -> String {
+    let file_start = name.rfind('/').map_or(0, |i| i + 1);
+    let (stem, extension) = match name[file_start..].rfind('.') {
+        Some(dot) => name.split_at(file_start + dot),
+        None => (name, ""),
+    };
+    let suffix = width.map_or(String::new(), |width| format!("-{}w", width));
+    match new_extension {
+        Some(new_extension) => format!("{}{}.{}", stem, suffix, new_extension),
+        None => format!("{}{}{}", stem, suffix, extension),
+    }
+}
+
+/// The width and height in pixels of the JPEG, PNG, GIF or WebP image at `path`,
+/// read from its header.
+pub(crate) fn dimensions(path: &Path) -> Option<(u32, u32)> {
+    let bytes = std::fs::read(path).ok()?;
+    let be16 = |i: usize| Some(u16::from_be_bytes(bytes.get(i..i + 2)?.try_into().ok()?) as u32);
+    let le16 = |i: usize| Some(u16::from_le_bytes(bytes.get(i..i + 2)?.try_into().ok()?) as u32);
+    let be32 = |i: usize| Some(u32::from_be_bytes(bytes.get(i..i + 4)?.try_into().ok()?));
+    let le24 = |i: usize| {
+        let b = bytes.get(i..i + 3)?;
+        Some(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16)
+    };
+
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some((be32(16)?, be32(20)?));
+    }
+    if bytes.starts_with(b"GIF8") {
+        return Some((le16(6)?, le16(8)?));
+    }
+    if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        return match bytes.get(12..16)? {
+            b"VP8 " => Some((le16(26)? & 0x3fff, le16(28)? & 0x3fff)),
+            b"VP8L" => {
+                let b = bytes.get(21..25)?;
+                let (b0, b1, b2, b3) = (b[0] as u32, b[1] as u32, b[2] as u32, b[3] as u32);
+                Some((
+                    1 + ((b1 & 0x3f) << 8 | b0),
+                    1 + ((b3 & 0xf) << 10 | b2 << 2 | (b1 & 0xc0) >> 6),
+                ))
+            }
+            b"VP8X" => Some((1 + le24(24)?, 1 + le24(27)?)),
+            _ => None,
+        };
+    }
+    if bytes.starts_with(&[0xff, 0xd8]) {
+        // walk the segments up to the start of frame, which has the size
+        let mut i = 2;
+        while *bytes.get(i)? == 0xff {
+            let marker = *bytes.get(i + 1)?;
+            match marker {
+                0xff => i += 1,
+                0xd0..=0xd9 | 0x01 => i += 2,
+                0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                    return Some((be16(i + 7)?, be16(i + 5)?));
+                }
+                _ => i += 2 + be16(i + 2)? as usize,
+            }
+        }
+    }
+    None
+}
+
+/// `html` with each `<img>` tag replaced by what `rewrite` returns for it.
+pub(crate) fn rewrite_img_tags(html: &str, mut rewrite: impl FnMut(&str) -> String) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = find_tag(rest, "img") {
+        out.push_str(&rest[..start]);
+        let end = tag_end(&rest[start..]).map_or(rest.len(), |end| start + end);
+        out.push_str(&rewrite(&rest[start..end]));
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Every `<name>` tag in `html`, in order.
+pub(crate) fn tags<'a>(html: &'a str, name: &str) -> Vec<&'a str> {
+    let mut tags = vec![];
+    let mut rest = html;
+    while let Some(start) = find_tag(rest, name) {
+        let end = tag_end(&rest[start..]).map_or(rest.len(), |end| start + end);
+        tags.push(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    tags
+}
+
+/// Every `<name>` element in `html`, in order, as its opening tag and
+/// everything up to its closing tag.
+pub(crate) fn elements<'a>(html: &'a str, name: &str) -> Vec<(&'a str, &'a str)> {
+    let close = format!("</{}", name);
+    let mut elements = vec![];
+    let mut rest = html;
+    while let Some(start) = find_tag(rest, name) {
+        let end = tag_end(&rest[start..]).map_or(rest.len(), |end| start + end);
+        let contents_len = rest[end..]
+            .to_ascii_lowercase()
+            .find(&close)
+            .unwrap_or(rest.len() - end);
+        elements.push((&rest[start..end], &rest[end..end + contents_len]));
+        rest = &rest[end + contents_len..];
+    }
+    elements
+}
+
+fn find_tag(html: &str, name: &str) -> Option<usize> {
+    let open = format!("<{}", name);
+    html.as_bytes().windows(open.len() + 1).position(|window| {
+        let (start, after) = window.split_at(open.len());
+        start.eq_ignore_ascii_case(open.as_bytes())
+            && (after[0].is_ascii_whitespace() || after[0] == b'/' || after[0] == b'>')
+    })
+}
+
+/// Length of the tag `html` starts with, up to and including its `>`.
+fn tag_end(html: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '>') => return Some(i + 1),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// The value of attribute `name` of `tag`, as written, or `""` if it has no value.
+pub(crate) fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag.trim_start_matches('<');
+    // skip the element name
+    rest = rest.trim_start_matches(|c: char| !c.is_ascii_whitespace() && c != '>' && c != '/');
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() || rest.starts_with('>') {
+            return None;
+        }
+        let name_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(rest.len());
+        let attribute_name = &rest[..name_end];
+        rest = rest[name_end..].trim_start();
+
+        let value = match rest.strip_prefix('=') {
+            Some(after) => {
+                let after = after.trim_start();
+                let (value, remaining) = match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let inner = &after[1..];
+                        let end = inner.find(quote).unwrap_or(inner.len());
+                        (&inner[..end], inner.get(end + 1..).unwrap_or(""))
+                    }
+                    _ => {
+                        let end = after
+                            .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                            .unwrap_or(after.len());
+                        after.split_at(end)
+                    }
+                };
+                rest = remaining;
+                value
+            }
+            None => "",
+        };
+        if attribute_name.eq_ignore_ascii_case(name) {
+            return Some(value);
+        }
+    }
+}
+
+/// `tag` with each of `attributes` it doesn't already have added at the end.
+pub(crate) fn with_attributes(tag: &str, attributes: &[(&str, String)]) -> String {
+    let (open, close) = match tag.strip_suffix("/>") {
+        Some(open) => (open.trim_end(), " />"),
+        None => (tag.strip_suffix('>').unwrap_or(tag).trim_end(), ">"),
+    };
+    let mut out = open.to_string();
+    for (name, value) in attributes {
+        if attribute(tag, name).is_none() {
+            out.push_str(&format!(
+                " {}=\"{}\"",
+                name,
+                maud::html! { (value) }.into_string()
+            ));
+        }
+    }
+    out.push_str(close);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    #[test]
+    fn adds_srcsets_to_large_local_images() {
+        let dir = crate::test_dir::TestDir::new("images");
+        // enough of a PNG to have a size: the signature, then IHDR for 1200x800
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend(1200u32.to_be_bytes());
+        png.extend(800u32.to_be_bytes());
+        std::fs::write(dir.join("cat.png"), &png).unwrap();
+        assert_eq!(super::dimensions(&dir.join("cat.png")), Some((1200, 800)));
+
+        let mut settings = crate::config::Images {
+            widths: vec![480, 960, 1920],
+            ..Default::default()
+        };
+        let local_file = |src: &str| (!src.contains("://")).then(|| dir.join(src));
+        let html = "<p><img src=\"cat.png\" alt=\"A cat\" /> <img src=\"https://example.com/dog.png\"></p>";
+        let (rewritten, variants) = super::responsive(html, &settings, local_file);
+
+        assert_eq!(
+            rewritten,
+            "<p><img src=\"cat.png\" alt=\"A cat\" srcset=\"cat-480w.png 480w, cat-960w.png 960w, cat.png 1200w\" sizes=\"100vw\" /> \
+             <img src=\"https://example.com/dog.png\"></p>"
+        );
+        let paths: Vec<PathBuf> = variants.into_iter().map(|variant| variant.path).collect();
+        assert_eq!(paths, [dir.join("cat-480w.png"), dir.join("cat-960w.png")]);
+
+        settings.widths = vec![];
+        settings.formats = vec![crate::config::ImageFormat::Webp];
+        let (rewritten, variants) = super::responsive(html, &settings, local_file);
+        assert_eq!(
+            rewritten,
+            "<p><picture><source type=\"image/webp\" srcset=\"cat.webp 1200w\">\
+             <img src=\"cat.png\" alt=\"A cat\" /></picture> \
+             <img src=\"https://example.com/dog.png\"></p>"
+        );
+        assert_eq!(variants[0].path, dir.join("cat.webp"));
+        assert_eq!(variants[0].width, None);
+    }
+
+    #[test]
+    fn lazy_loads_images() {
+        let dir = crate::test_dir::TestDir::new("lazy");
+        let gif = dir.join("dog.gif");
+        std::fs::write(&gif, b"GIF89a\x40\x01\xf0\x00").unwrap();
+        let local_file = |src: &str| (src == "dog.gif").then(|| gif.clone());
+
+        assert_eq!(
+            super::lazy_load(
+                "<img src=\"dog.gif\" alt=\"\"><img src=\"x.png\" loading=\"eager\" width=\"10\">",
+                local_file
+            ),
+            "<img src=\"dog.gif\" alt=\"\" loading=\"lazy\" decoding=\"async\" width=\"320\" height=\"240\">\
+             <img src=\"x.png\" loading=\"eager\" width=\"10\" decoding=\"async\">"
+        );
+    }
+
+    #[test]
+    fn reads_img_attributes() {
+        let tag = "<IMG alt='say \"hi\"' src=cat.png hidden>";
+        assert_eq!(super::attribute(tag, "src"), Some("cat.png"));
+        assert_eq!(super::attribute(tag, "alt"), Some("say \"hi\""));
+        assert_eq!(super::attribute(tag, "hidden"), Some(""));
+        assert_eq!(super::attribute(tag, "width"), None);
+    }
+}
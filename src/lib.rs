@@ -0,0 +1,3097 @@
+//! Builds a static blog from markdown posts and pages.
+//!
+//! [`Site`] is the whole generator: open a site directory, then build,
+//! serve or publish it. [`parse_post`], [`parse_page`], [`render_post`],
+//! [`render_page`] and [`feed`] are its parts, for tools like editors
+//! that want to preview a single document.
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, Utc};
+use glob::glob;
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+use pulldown_cmark::{html, Event, Options, Parser};
+use rayon::prelude::*;
+use rss::{ChannelBuilder, ItemBuilder};
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+mod activitypub;
+mod anchors;
+mod assets;
+mod changelog;
+mod charts;
+mod comments;
+mod compress;
+mod config;
+mod csp;
+mod deploy;
+mod encrypt;
+mod footnotes;
+mod front_matter;
+mod highlight;
+mod hooks;
+mod images;
+mod link_graph;
+mod manifest;
+mod math;
+mod minify;
+mod netlify;
+mod og_image;
+mod plugins;
+mod readability;
+mod redirects;
+mod s3;
+mod search;
+mod serve;
+mod sitemap;
+mod templates;
+#[cfg(test)]
+mod test_dir;
+mod urls;
+mod watch;
+mod webmentions;
+
+pub use config::Config;
+
+/// The built-in layout's inline CSS; like any inline `<style>`, its hash is
+/// allowed by the page's Content-Security-Policy.
+const INLINE_STYLE: &str = "html{visibility: hidden;opacity:0;}.site-header{border-block-end:2px dotted var(--graphical-fg);}.post-list{font-family:sans-serif;}.post-list tbody{border-block:none;}";
+
+/// How many recent additions and edits the changelog page lists.
+const CHANGELOG_ENTRIES: usize = 50;
+
+/// Everything in a post body before this marker is its excerpt,
+/// unless the front matter has an `excerpt`.
+const MORE_MARKER: &str = "<!-- more -->";
+
+/// Posts shorter than this many words are flagged as stubs in the lengths report.
+pub const STUB_WORDS: usize = 150;
+
+/// Feed summaries of posts without an excerpt are cut off after this many words.
+const SUMMARY_WORDS: usize = 60;
+
+/// A typical adult's silent reading speed, for estimating reading time.
+const WORDS_PER_MINUTE: usize = 230;
+
+/// The page hosts like GitHub Pages and Netlify serve for missing URLs;
+/// `pages/404.md` replaces the built-in one.
+const NOT_FOUND_PAGE: &str = "404";
+
+/// A post from `posts/`, parsed and with its body rendered.
+pub struct Post {
+    /// The front matter's `slug`, or the title slugified
+    slug: String,
+    /// Source file stem, which git and so the changelog know the post by
+    file_stem: String,
+    /// URL path of the rendered post, from the `permalink` pattern
+    path: String,
+    /// Absolute URL of the rendered post
+    permalink: String,
+    title: String,
+    created_on: chrono::NaiveDate,
+    /// Date of the last significant edit, if the front matter gives one
+    updated_on: Option<chrono::NaiveDate>,
+    /// URL of the toot announcing this post, whose replies are shown as comments
+    mastodon: Option<String>,
+    /// URL or `at://` URI of the Bluesky post announcing this post, likewise
+    bluesky: Option<String>,
+    /// Whether the site's comment embed is shown under the post
+    comments: bool,
+    /// When set, the body is encrypted with this passphrase and the post is left out of the feed
+    password: Option<String>,
+    /// Hash of the source file, to tell whether the output needs rebuilding
+    source_hash: String,
+    /// Unfinished; only built when drafts are enabled
+    draft: bool,
+    /// Flesch-Kincaid grade level of the body text
+    readability: f64,
+    words: usize,
+    tags: Vec<String>,
+    /// Destinations of every link in the body
+    links: Vec<String>,
+    /// URL paths that redirect to this post
+    aliases: Vec<String>,
+    /// Absolute URL of the post's social preview image, if it has one
+    og_image: Option<String>,
+    /// Stylesheets from the post's asset directory to link on its page only
+    stylesheets: Vec<String>,
+    /// Scripts from the post's asset directory to load on its page only
+    scripts: Vec<String>,
+    /// Hrefs of the copied, fingerprinted `stylesheets` and `scripts`
+    assets: assets::PostAssets,
+    /// Summary shown in listings and used as the feed item description
+    excerpt: Option<Markup>,
+    /// Links to the body's headings, when the front matter asks for them
+    toc: Option<Markup>,
+    /// Whether the body has math for KaTeX to typeset
+    math: bool,
+    /// Whether the body has mermaid diagrams to draw
+    diagrams: bool,
+    /// Plain text for the search index: the body, or for a locked post its excerpt
+    text: String,
+    body: Markup,
+}
+
+impl Post {
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Name of the post's page, from its front matter or its title
+    pub fn slug(&self) -> &str {
+        &self.slug
+    }
+
+    /// Absolute URL of the post's page
+    pub fn permalink(&self) -> &str {
+        &self.permalink
+    }
+
+    pub fn created_on(&self) -> chrono::NaiveDate {
+        self.created_on
+    }
+
+    pub fn updated_on(&self) -> Option<chrono::NaiveDate> {
+        self.updated_on
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn is_draft(&self) -> bool {
+        self.draft
+    }
+
+    /// The body, rendered to HTML
+    pub fn html(&self) -> &str {
+        &self.body.0
+    }
+
+    /// When the post last changed, as far as readers are concerned.
+    fn last_modified(&self) -> chrono::NaiveDate {
+        self.updated_on.unwrap_or(self.created_on)
+    }
+
+    /// Estimated minutes to read the body, rounded up.
+    fn reading_minutes(&self) -> usize {
+        self.words.div_ceil(WORDS_PER_MINUTE).max(1)
+    }
+}
+
+/// A page from `pages/`, parsed and with its body rendered.
+pub struct Page {
+    /// Source file stem; names the output file
+    slug: String,
+    title: String,
+    /// Whether the body has math for KaTeX to typeset
+    math: bool,
+    /// Whether the body has mermaid diagrams to draw
+    diagrams: bool,
+    body: Markup,
+}
+
+impl Page {
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn slug(&self) -> &str {
+        &self.slug
+    }
+
+    /// The body, rendered to HTML
+    pub fn html(&self) -> &str {
+        &self.body.0
+    }
+}
+
+fn md_to_html(markdown_str: &str, options: Options) -> Markup {
+    let parser = highlight::highlight_code_blocks(Parser::new_ext(markdown_str, options));
+    let mut html_buf = String::new();
+    html::push_html(&mut html_buf, parser);
+    maud::PreEscaped(html_buf)
+}
+
+/// Like `md_to_html`, but headings get ids and `#` links and footnotes are
+/// gathered at the end with backlinks; also returns the headings.
+fn md_to_anchored_html(markdown_str: &str, options: Options) -> (Markup, Vec<anchors::Heading>) {
+    let (events, headings) = anchors::heading_anchors(
+        footnotes::footnotes(highlight::highlight_code_blocks(Parser::new_ext(
+            markdown_str,
+            options,
+        )))
+        .into_iter(),
+    );
+    let mut html_buf = String::new();
+    html::push_html(&mut html_buf, events.into_iter());
+    (maud::PreEscaped(html_buf), headings)
+}
+
+/// The prose of a markdown document, without markup.
+fn md_to_text(markdown_str: &str, options: Options) -> String {
+    let mut text = String::new();
+    for event in Parser::new_ext(markdown_str, options) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => text.push('\n'),
+            _ => (),
+        }
+    }
+    text
+}
+
+/// Absolute URL of the page named `slug`.
+fn permalink(config: &Config, slug: &str) -> String {
+    format!("{}{}", config.base_url, page_path(config, slug))
+}
+
+/// URL path of the page named `slug`.
+fn page_path(config: &Config, slug: &str) -> String {
+    // hosts look for exactly `/404.html`
+    if config.pretty_urls && slug != NOT_FOUND_PAGE {
+        format!("/{}/", slug)
+    } else {
+        format!("/{}.html", slug)
+    }
+}
+
+/// URL path of the page listing posts tagged `tag`.
+fn tag_path(config: &Config, tag: &str) -> String {
+    page_path(config, &format!("tags/{}", tag_slug(tag)))
+}
+
+/// URL path of the page listing the posts from `year`.
+fn archive_year_path(config: &Config, year: i32) -> String {
+    page_path(config, &format!("archive/{}", year))
+}
+
+/// The configured `permalink` pattern filled in for a post.
+fn post_path(config: &Config, slug: &str, created_on: chrono::NaiveDate) -> String {
+    let pattern = match config.permalink.strip_suffix(".html") {
+        Some(stem) if config.pretty_urls => format!("{}/", stem),
+        _ => config.permalink.clone(),
+    };
+    pattern
+        .replace(":year", &created_on.format("%Y").to_string())
+        .replace(":month", &created_on.format("%m").to_string())
+        .replace(":day", &created_on.format("%d").to_string())
+        .replace(":slug", slug)
+}
+
+/// Where the page at URL `path` is written: the file itself, or for a
+/// directory path, its `index.html`.
+fn output_file(build_dir: &Path, path: &str) -> PathBuf {
+    let relative = path.trim_start_matches('/');
+    if relative.is_empty() || relative.ends_with('/') {
+        build_dir.join(relative).join("index.html")
+    } else {
+        build_dir.join(relative)
+    }
+}
+
+/// Parses a post from its source `s`. `file_stem` is the slug of last resort,
+/// for posts with no `slug` whose title has nothing to slugify.
+pub fn parse_post(config: &Config, file_stem: &str, s: &str) -> Result<Post> {
+    let (front_matter, body) = front_matter::parse::<front_matter::PostFrontMatter>(s)?;
+    let slug = match front_matter.slug {
+        Some(slug) => {
+            check_slug(&slug)?;
+            slug
+        }
+        None => match title_slug(&front_matter.title) {
+            slug if slug.is_empty() => file_stem.to_string(),
+            slug => slug,
+        },
+    };
+    let mut options = config.markdown.options();
+    options.set(
+        Options::ENABLE_MATH,
+        config.markdown.math || front_matter.math,
+    );
+    let text = md_to_text(body, options);
+
+    // the body of a locked post is secret, so it only gets an excerpt if one is given explicitly
+    let excerpt = match (&front_matter.excerpt, &front_matter.password) {
+        (Some(excerpt), _) => Some(md_to_html(excerpt, options)),
+        (None, None) => body
+            .split_once(MORE_MARKER)
+            .map(|(before, _)| md_to_html(before, options)),
+        (None, Some(_)) => None,
+    };
+    let searchable_text = match (&front_matter.excerpt, &front_matter.password) {
+        (_, None) => text.clone(),
+        (Some(excerpt), Some(_)) => md_to_text(excerpt, options),
+        (None, Some(_)) => String::new(),
+    };
+
+    let path = post_path(config, &slug, front_matter.created);
+    let (body_html, headings) = md_to_anchored_html(body, options);
+    // a locked post's headings are as secret as the rest of its body
+    let toc = (front_matter.toc && front_matter.password.is_none() && !headings.is_empty())
+        .then(|| anchors::table_of_contents(&headings));
+
+    Ok(Post {
+        permalink: format!("{}{}", config.base_url, path),
+        path,
+        slug,
+        file_stem: file_stem.to_string(),
+        title: front_matter.title,
+        created_on: front_matter.created,
+        updated_on: front_matter.updated,
+        mastodon: front_matter.mastodon,
+        bluesky: front_matter.bluesky,
+        comments: front_matter.comments,
+        password: front_matter.password,
+        source_hash: manifest::hash(&[s.as_bytes()]),
+        draft: front_matter.draft,
+        tags: front_matter.tags,
+        readability: readability::flesch_kincaid_grade(&text),
+        words: text.split_whitespace().count(),
+        links: link_graph::links(body),
+        aliases: front_matter
+            .aliases
+            .iter()
+            .map(|alias| redirects::alias_path(alias))
+            .collect::<Result<_>>()?,
+        og_image: None,
+        stylesheets: front_matter.css,
+        scripts: front_matter.js,
+        assets: assets::PostAssets::default(),
+        excerpt,
+        text: searchable_text,
+        toc,
+        math: math::has_math(body, options),
+        diagrams: highlight::has_diagrams(body, options),
+        body: body_html,
+    })
+}
+
+/// Parses a page from its source `s`; `slug` names its output file.
+pub fn parse_page(config: &Config, slug: &str, s: &str) -> Result<Page> {
+    let (front_matter, body) = front_matter::parse::<front_matter::PageFrontMatter>(s)?;
+    let mut options = config.markdown.options();
+    options.set(
+        Options::ENABLE_MATH,
+        config.markdown.math || front_matter.math,
+    );
+
+    Ok(Page {
+        slug: slug.to_string(),
+        title: front_matter.title,
+        math: math::has_math(body, options),
+        diagrams: highlight::has_diagrams(body, options),
+        body: md_to_anchored_html(body, options).0,
+    })
+}
+
+/// The file stem of a source file, which names its output file.
+fn slug(path: &Path) -> String {
+    path.file_stem()
+        .expect("Could not get file stem of source file")
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn get_markdown_files(path: &Path) -> Result<glob::Paths, glob::PatternError> {
+    let mdpath = path.join("**/*.md");
+    let mdpathstr = mdpath
+        .to_str()
+        .expect("must be able to convert path to str");
+    glob(mdpathstr)
+}
+
+macro_rules! layout {
+    ($config:expr, $title:expr, $content:expr) => {
+        layout!($config, $title, html! {}, $content)
+    };
+    ($config:expr, $title:expr, $head:expr, $content:expr) => {
+        match &$config.templates {
+            Some(templates) => templates.layout(
+                $config,
+                $title,
+                html! {
+                    meta http-equiv="Content-Security-Policy" content=(csp::PLACEHOLDER);
+                    link rel="alternate" type="application/rss+xml" title=($config.feed_title()) href="/feed";
+                    link rel="stylesheet" href=(format!("/{}", highlight::STYLESHEET)) type="text/css";
+                    @if let Some(endpoint) = &$config.webmentions.endpoint {
+                        link rel="webmention" href=(endpoint);
+                    }
+                    ($head)
+                    @if $config.live_reload {
+                        script src=(serve::LIVE_RELOAD_SCRIPT_PATH) {}
+                    }
+                },
+                $content,
+            ),
+            None => Ok(html! {
+                (DOCTYPE)
+                html lang="en" {
+                    head {
+                        meta charset="utf-8";
+                        meta http-equiv="Content-Security-Policy" content=(csp::PLACEHOLDER);
+                        meta content="IE=edge,chrome=1" http-equiv="X-UA-Compatible";
+                        title { ($title) }
+                        meta name="description" content=($config.description);
+                        meta content="width=device-width" name="viewport";
+                        link rel="icon" href="/favicon-min.png" type="image.png";
+                        link rel="alternate" type="application/rss+xml" title=($config.feed_title()) href="/feed";
+                        style {
+                            (INLINE_STYLE)
+                        }
+                        link rel="stylesheet" href="/missing.min.css?version=1.1.3" type="text/css";
+                        link rel="stylesheet" href="/style.css" type="text/css";
+                        link rel="stylesheet" href=(format!("/{}", highlight::STYLESHEET)) type="text/css";
+                        @if let Some(endpoint) = &$config.webmentions.endpoint {
+                            link rel="webmention" href=(endpoint);
+                        }
+                        ($head)
+                        @if $config.live_reload {
+                            script src=(serve::LIVE_RELOAD_SCRIPT_PATH) {}
+                        }
+                    }
+                    body class="margin center" {
+                        div {
+                            header class="site-header" {
+                                h1 {
+                                    a href="/index.html" {
+                                        ($config.title)
+                                    }
+                                }
+
+                                nav class="contents" aria-label="Site sections" {
+                                    @for (i, link) in $config.nav.iter().enumerate() {
+                                        @if i > 0 {
+                                            " "
+                                        }
+                                        a href=(link.href) {
+                                            (link.title)
+                                        }
+                                    }
+                                }
+                            }
+                            ($content)
+                            div {
+                                p {
+                                    @for (i, link) in $config.footer.iter().enumerate() {
+                                        @if i > 0 {
+                                            " "
+                                        }
+                                        a href=(link.href) {
+                                            (link.title)
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }),
+        }
+    };
+}
+
+fn page(config: &Config, title: &str, content: &Markup) -> Result<Markup> {
+    layout!(
+        config,
+        title,
+        html! {
+            div {
+                h1 { (title) }
+                div { (content) }
+            }
+        }
+    )
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters,
+/// for use in `mailto:` query parameters.
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn reply_mailto(email: &str, title: &str, permalink: &str) -> String {
+    format!(
+        "mailto:{}?subject={}&body={}",
+        email,
+        percent_encode(&format!("Re: {}", title)),
+        percent_encode(permalink)
+    )
+}
+
+fn reply_link(config: &Config, title: &str, permalink: &str) -> Markup {
+    html! {
+        @if let Some(email) = &config.reply_email {
+            p {
+                a href=(reply_mailto(email, title, permalink)) {
+                    "Reply via email"
+                }
+            }
+        }
+    }
+}
+
+fn comments_section(
+    sources: &[comments::Source],
+    file_comments: &[comments::FileComment],
+    comments: &[comments::Comment],
+) -> Markup {
+    html! {
+        div {
+            h3 { "Comments" }
+            @for comment in file_comments {
+                div id=(format!("comment-{}", comment.id)) {
+                    p {
+                        @match &comment.author_url {
+                            Some(url) => (mention_link(url, &comment.author)),
+                            None => (comment.author),
+                        }
+                        " - "
+                        a href=(format!("#comment-{}", comment.id)) { (comment.date.format("%Y-%m-%d")) }
+                    }
+                    (comment.body)
+                }
+            }
+            @for comment in comments {
+                div {
+                    p {
+                        (mention_link(&comment.author_url, &comment.author))
+                        " - "
+                        (mention_link(&comment.url, &comment.created_at))
+                    }
+                    @for paragraph in comment.text.split("\n\n") {
+                        p { (paragraph) }
+                    }
+                }
+            }
+            @if !sources.is_empty() {
+                p {
+                    @for (i, source) in sources.iter().enumerate() {
+                        @if i > 0 {
+                            " "
+                        }
+                        a href=(source.reply_url()) { "Reply on " (source.name()) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `text` linking to `url`, unless it isn't a web URL.
+fn mention_link(url: &str, text: &str) -> Markup {
+    html! {
+        @if webmentions::is_web_url(url) {
+            a href=(url) { (text) }
+        } @else {
+            (text)
+        }
+    }
+}
+
+/// Webmentions a post has received: who liked, reposted and bookmarked it,
+/// then replies and mentions in full.
+fn webmentions_section(mentions: &[&webmentions::Mention]) -> Markup {
+    let by = |kind| -> Vec<&webmentions::Mention> {
+        mentions
+            .iter()
+            .copied()
+            .filter(|mention| mention.kind == kind)
+            .collect()
+    };
+    let reactions = [
+        ("Liked by ", by(webmentions::Kind::Like)),
+        ("Reposted by ", by(webmentions::Kind::Repost)),
+        ("Bookmarked by ", by(webmentions::Kind::Bookmark)),
+    ];
+    html! {
+        div {
+            h3 { "Reactions" }
+            @for (label, reactions) in &reactions {
+                @if !reactions.is_empty() {
+                    p {
+                        (label)
+                        @for (i, reaction) in reactions.iter().enumerate() {
+                            @if i > 0 {
+                                ", "
+                            }
+                            (mention_link(&reaction.url, &reaction.author))
+                        }
+                    }
+                }
+            }
+            @for mention in mentions {
+                @if matches!(mention.kind, webmentions::Kind::Reply | webmentions::Kind::Mention) {
+                    div {
+                        p {
+                            (mention_link(&mention.author_url, &mention.author))
+                            @if mention.kind == webmentions::Kind::Mention {
+                                " mentioned this"
+                            }
+                            " - "
+                            (mention_link(&mention.url, &mention.published))
+                        }
+                        @if mention.kind == webmentions::Kind::Reply {
+                            @for paragraph in mention.text.split("\n\n") {
+                                p { (paragraph) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Open Graph and Twitter Card tags, so links shared on social media get a preview.
+/// `og_type` is `article` for posts and `website` for everything else.
+fn social_meta(
+    config: &Config,
+    title: &str,
+    url: &str,
+    description: &str,
+    og_type: &str,
+    image: Option<&str>,
+) -> Markup {
+    html! {
+        meta property="og:site_name" content=(config.title);
+        meta property="og:title" content=(title);
+        meta property="og:type" content=(og_type);
+        meta property="og:url" content=(url);
+        meta property="og:description" content=(description);
+        @if let Some(image) = image {
+            meta property="og:image" content=(image);
+            meta property="og:image:width" content=(og_image::WIDTH);
+            meta property="og:image:height" content=(og_image::HEIGHT);
+            meta name="twitter:card" content="summary_large_image";
+            meta name="twitter:image" content=(image);
+        } @else {
+            meta name="twitter:card" content="summary";
+        }
+        meta name="twitter:title" content=(title);
+        meta name="twitter:description" content=(description);
+    }
+}
+
+/// A schema.org `BlogPosting` describing `post`, as JSON-LD, for search engines.
+fn structured_data(config: &Config, post: &Post, description: &str) -> Markup {
+    let mut posting = serde_json::json!({
+        "@context": "https://schema.org",
+        "@type": "BlogPosting",
+        "headline": post.title,
+        "description": description,
+        "url": post.permalink,
+        "mainEntityOfPage": post.permalink,
+        "datePublished": post.created_on,
+        "dateModified": post.last_modified(),
+        "author": {
+            "@type": "Person",
+            "name": config.author.as_deref().unwrap_or(&config.title),
+            "url": config.base_url,
+        },
+    });
+    if let Some(image) = &post.og_image {
+        posting["image"] = image.as_str().into();
+    }
+    // `</script>` in a title mustn't end the script element early
+    let json = posting.to_string().replace("</", "<\\/");
+    html! {
+        script type="application/ld+json" { (PreEscaped(json)) }
+    }
+}
+
+/// A page rendered from `pages/`.
+fn markdown_page(config: &Config, page: &Page) -> Result<Markup> {
+    let url = permalink(config, &page.slug);
+
+    layout!(
+        config,
+        &page.title,
+        html! {
+            link rel="canonical" href=(url);
+            (social_meta(config, &page.title, &url, &config.description, "website", None))
+            @if page.math {
+                (math::head(config))
+            }
+            @if page.diagrams {
+                script src=(config.markdown.mermaid) defer {}
+            }
+        },
+        html! {
+            div {
+                h1 { (page.title) }
+                div { (page.body) }
+            }
+        }
+    )
+}
+
+/// Renders `post` with `content` standing in for its body, which may
+/// have been encrypted.
+fn post(
+    config: &Config,
+    post: &Post,
+    content: &Markup,
+    comments: Option<&Markup>,
+    related: &[&Post],
+    neighbours: Neighbours,
+) -> Result<Markup> {
+    let description = match &post.excerpt {
+        Some(excerpt) => comments::html_to_text(&excerpt.0)
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" "),
+        None => config.description.clone(),
+    };
+
+    layout!(
+        config,
+        &post.title,
+        html! {
+            link rel="canonical" href=(post.permalink);
+            (social_meta(config, &post.title, &post.permalink, &description, "article", post.og_image.as_deref()))
+            (structured_data(config, post, &description))
+            // locked posts aren't published as ActivityPub objects
+            @if config.activitypub.is_some() && post.password.is_none() {
+                link rel="alternate" type="application/activity+json" href=(activitypub::object_url(&config.base_url, &post.slug));
+            }
+            @for stylesheet in &post.assets.stylesheets {
+                link rel="stylesheet" href=(stylesheet) type="text/css";
+            }
+            @for script in &post.assets.scripts {
+                script src=(script) defer {}
+            }
+            @if post.math {
+                (math::head(config))
+            }
+            @if post.diagrams {
+                script src=(config.markdown.mermaid) defer {}
+            }
+        },
+        html! {
+            // h-entry microformats, for webmention and IndieWeb readers
+            div class="h-entry" {
+                h2 class="p-name" { (PreEscaped(&post.title)) }
+                p {
+                    a class="u-url" href=(post.permalink) {
+                        time class="dt-published" datetime=(post.created_on.format("%Y-%m-%d")) {
+                            (post.created_on.format("%Y-%m-%d"))
+                        }
+                    }
+                    @if let Some(updated_on) = post.updated_on {
+                        " · updated "
+                        time class="dt-updated" datetime=(updated_on.format("%Y-%m-%d")) {
+                            (updated_on.format("%Y-%m-%d"))
+                        }
+                    }
+                    // a locked post's length would give away something of its body
+                    @if post.password.is_none() {
+                        " · " (post.words) " words, " (post.reading_minutes()) " min read"
+                    }
+                    @if !post.tags.is_empty() {
+                        " · " (tag_links(config, &post.tags))
+                    }
+                    a class="p-author h-card" href=(config.base_url) hidden {
+                        (config.author.as_deref().unwrap_or(&config.title))
+                    }
+                }
+                @if let Some(toc) = &post.toc {
+                    (toc)
+                }
+                div class="e-content" { (content) }
+                @if neighbours.older.is_some() || neighbours.newer.is_some() {
+                    nav aria-label="Older and newer posts" {
+                        @if let Some(older) = neighbours.older {
+                            a href=(older.path) rel="prev" { "← " (PreEscaped(&older.title)) }
+                        }
+                        @if neighbours.older.is_some() && neighbours.newer.is_some() {
+                            " · "
+                        }
+                        @if let Some(newer) = neighbours.newer {
+                            a href=(newer.path) rel="next" { (PreEscaped(&newer.title)) " →" }
+                        }
+                    }
+                }
+                @if !related.is_empty() {
+                    section {
+                        h3 { "Related posts" }
+                        ul {
+                            @for related in related {
+                                li { a href=(related.path) { (PreEscaped(&related.title)) } }
+                            }
+                        }
+                    }
+                }
+                @if let Some(comments) = comments {
+                    (comments)
+                }
+                (reply_link(config, &post.title, &post.permalink))
+            }
+        }
+    )
+}
+
+/// The posts published just before and just after a post.
+#[derive(Clone, Copy)]
+struct Neighbours<'a> {
+    older: Option<&'a Post>,
+    newer: Option<&'a Post>,
+}
+
+impl<'a> Neighbours<'a> {
+    /// Neighbours of `posts[i]`, where `posts` is sorted newest first.
+    fn of(posts: &'a [Post], i: usize) -> Neighbours<'a> {
+        Neighbours {
+            older: posts.get(i + 1),
+            newer: i.checked_sub(1).map(|newer| &posts[newer]),
+        }
+    }
+}
+
+/// The name of a tag's page: lowercased, with whitespace collapsed to dashes.
+fn tag_slug(tag: &str) -> String {
+    tag.to_lowercase()
+        .split(|c: char| c.is_whitespace() || c == '/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn tag_links(config: &Config, tags: &[String]) -> Markup {
+    html! {
+        @for (i, tag) in tags.iter().enumerate() {
+            @if i > 0 {
+                ", "
+            }
+            a class="p-category" href=(tag_path(config, tag)) { (tag) }
+        }
+    }
+}
+
+/// A post's row in the index, tag and archive listings, followed by its excerpt if it has one.
+fn index_link(config: &Config, post: &Post) -> Markup {
+    html! {
+        tr class="h-entry" {
+            td {
+                a class="p-name u-url" href=(post.path) {
+                    (PreEscaped(&post.title))
+                }
+            }
+            td {
+                time class="dt-published" datetime=(post.created_on.format("%Y-%m-%d")) {
+                    (post.created_on.format("%Y-%m-%d"))
+                }
+            }
+            td {
+                (tag_links(config, &post.tags))
+            }
+        }
+        @if let Some(excerpt) = &post.excerpt {
+            tr {
+                td colspan="3" {
+                    (excerpt)
+                }
+            }
+        }
+    }
+}
+
+fn tag_page(config: &Config, tag: &str, post_links: &[Markup]) -> Result<Markup> {
+    layout!(
+        config,
+        &format!("Posts tagged {}", tag),
+        html! {
+            h2 { "Posts tagged " (tag) }
+            table class="post-list" {
+                tbody {
+                    @for post_link in post_links {
+                        (post_link)
+                    }
+                }
+            }
+        }
+    )
+}
+
+/// Every post, newest first, under a heading for the year it was written.
+/// `years` is (year, post links), newest year first.
+fn archive(config: &Config, years: &[(i32, Vec<Markup>)]) -> Result<Markup> {
+    layout!(
+        config,
+        "Archive",
+        html! {
+            h2 { "Archive" }
+            @for (year, post_links) in years {
+                h3 {
+                    a href=(archive_year_path(config, *year)) { (year) }
+                    " (" (post_links.len()) ")"
+                }
+                table class="post-list" {
+                    tbody {
+                        @for post_link in post_links {
+                            (post_link)
+                        }
+                    }
+                }
+            }
+        }
+    )
+}
+
+fn archive_year(config: &Config, year: i32, post_links: &[Markup]) -> Result<Markup> {
+    layout!(
+        config,
+        &format!("Posts from {}", year),
+        html! {
+            h2 { "Posts from " (year) }
+            table class="post-list" {
+                tbody {
+                    @for post_link in post_links {
+                        (post_link)
+                    }
+                }
+            }
+        }
+    )
+}
+
+/// `tags` is (display name, post count).
+fn tags_index(config: &Config, tags: &[(&str, usize)]) -> Result<Markup> {
+    layout!(
+        config,
+        "Tags",
+        html! {
+            h2 { "Tags" }
+            ul {
+                @for (tag, count) in tags {
+                    li { a href=(tag_path(config, tag)) { (tag) } " (" (count) ")" }
+                }
+            }
+        }
+    )
+}
+
+fn search_page(config: &Config) -> Result<Markup> {
+    layout!(config, "Search", search::page())
+}
+
+/// The not-found page, for sites without a `pages/404.md`.
+fn not_found_page(config: &Config) -> Result<Markup> {
+    page(
+        config,
+        "Page not found",
+        &html! {
+            p { "There's nothing here. It may have moved, or never existed." }
+            p {
+                a href="/index.html" { "Go to the home page" }
+                " or "
+                a href="/search.html" { "search the posts" }
+                "."
+            }
+        },
+    )
+}
+
+/// Href of index page `number`, counting from 1.
+fn index_page_href(config: &Config, number: usize) -> String {
+    if number == 1 {
+        "/index.html".to_string()
+    } else {
+        page_path(config, &format!("page/{}", number))
+    }
+}
+
+/// Index page `page_number` of `page_count`, listing `post_links`.
+fn index(
+    config: &Config,
+    post_links: &[Markup],
+    page_number: usize,
+    page_count: usize,
+) -> Result<Markup> {
+    let title = if page_number == 1 {
+        config.description.clone()
+    } else {
+        format!("{} - page {}", config.description, page_number)
+    };
+
+    layout!(
+        config,
+        &title,
+        html! {
+            table class="post-list" {
+                tbody {
+                    @for post_link in post_links {
+                        (post_link)
+                    }
+                }
+            }
+            @if page_count > 1 {
+                nav aria-label="Pagination" {
+                    @if page_number > 1 {
+                        a href=(index_page_href(config, page_number - 1)) rel="prev" { "← Newer" }
+                        " "
+                    }
+                    "Page " (page_number) " of " (page_count)
+                    @if page_number < page_count {
+                        " "
+                        a href=(index_page_href(config, page_number + 1)) rel="next" { "Older →" }
+                    }
+                }
+            }
+        }
+    )
+}
+
+/// Posts only have dates, so feeds treat them as published at midnight UTC.
+fn midnight_utc(date: chrono::NaiveDate) -> chrono::DateTime<Utc> {
+    chrono::DateTime::<Utc>::from_naive_utc_and_offset(
+        date.and_time(chrono::NaiveTime::MIN),
+        chrono::Utc,
+    )
+}
+
+/// Items carry `<atom:updated>` for edited posts, which RSS has no element for.
+const ATOM_NAMESPACE: &str = "http://www.w3.org/2005/Atom";
+
+/// The site's RSS feed of `posts`, which are sorted newest first. Locked
+/// posts are left out, and at most `[feed] limit` posts are included.
+pub fn feed(config: &Config, posts: &[Post]) -> String {
+    let unlocked = || posts.iter().filter(|post| post.password.is_none());
+    let mut items: Vec<rss::Item> = unlocked().map(|post| rss_item(config, post)).collect();
+    if config.feed.limit > 0 {
+        items.truncate(config.feed.limit);
+    }
+    ChannelBuilder::default()
+        .title(config.feed_title())
+        .link(&config.base_url)
+        .description(config.feed_description())
+        .namespace(("atom".to_string(), ATOM_NAMESPACE.to_string()))
+        .items(items)
+        .last_build_date(
+            unlocked()
+                .map(Post::last_modified)
+                .max()
+                .map(|date| midnight_utc(date).to_rfc2822()),
+        )
+        .build()
+        .to_string()
+}
+
+fn rss_item(config: &Config, post: &Post) -> rss::Item {
+    let dt = midnight_utc(post.created_on).to_rfc2822();
+    let mut extensions = rss::extension::ExtensionMap::new();
+    if let Some(updated_on) = post.updated_on {
+        let updated = rss::extension::ExtensionBuilder::default()
+            .name("atom:updated")
+            .value(midnight_utc(updated_on).to_rfc3339())
+            .build();
+        extensions
+            .entry("atom".to_string())
+            .or_default()
+            .insert("updated".to_string(), vec![updated]);
+    }
+
+    let (description, content) = if config.feed.summary {
+        let summary = match &post.excerpt {
+            Some(excerpt) => excerpt.0.clone(),
+            None => {
+                let words: Vec<&str> = post.text.split_whitespace().collect();
+                let ellipsis = if words.len() > SUMMARY_WORDS {
+                    "…"
+                } else {
+                    ""
+                };
+                let start = words[..words.len().min(SUMMARY_WORDS)].join(" ");
+                html! { p { (start) (ellipsis) } }.into_string()
+            }
+        };
+        (Some(summary), None)
+    } else {
+        let mut content = post.body.0.clone();
+        if config.reply_email_in_feed {
+            content.push_str(&reply_link(config, &post.title, &post.permalink).0);
+        }
+        (
+            post.excerpt.as_ref().map(|excerpt| excerpt.0.clone()),
+            Some(content),
+        )
+    };
+
+    // feed readers resolve relative URLs against the feed, if at all
+    let absolutize = |html: String| urls::absolutize(&html, &post.permalink);
+
+    ItemBuilder::default()
+        .title(post.title.clone())
+        .link(post.permalink.clone())
+        .description(description.map(absolutize))
+        .content(content.map(absolutize))
+        .pub_date(dt)
+        .extensions(extensions)
+        .build()
+}
+
+/// Settings for one build, beyond those in the site's `stanley.toml`.
+#[derive(Debug, Default, Clone)]
+pub struct BuildOptions {
+    /// Also build posts marked `draft: true`
+    pub drafts: bool,
+    /// Rebuild every output, even those whose inputs haven't changed
+    pub force: bool,
+    /// Also build posts dated after today
+    pub future: bool,
+    /// Strip comments and collapse whitespace in generated HTML
+    pub minify: bool,
+    /// Whether pages reload themselves after a rebuild; set by `Site::serve`
+    /// when watching
+    pub live_reload: bool,
+}
+
+/// A site directory, with `posts/`, `pages/`, `static/` and a `stanley.toml`,
+/// and where it's built to.
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// let site = stanley::Site::open("my-blog", None)?;
+/// site.build(&stanley::BuildOptions::default())?;
+/// println!("Built {:?}", site.build_dir());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Site {
+    dir: PathBuf,
+    build_dir: PathBuf,
+    before_build: Vec<Hook>,
+    after_build: Vec<Hook>,
+}
+
+/// A function run before or after each build of a `Site`; returning an
+/// error fails the build.
+pub type Hook = Arc<dyn Fn(&Site) -> Result<()> + Send + Sync>;
+
+impl std::fmt::Debug for Site {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Site")
+            .field("dir", &self.dir)
+            .field("build_dir", &self.build_dir)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How long the published posts are, from `Site::lengths`, for auditing old
+/// content. Locked posts are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LengthReport {
+    /// (range of word counts, e.g. `250-499`, number of posts), shortest first
+    pub buckets: Vec<(String, usize)>,
+    /// Posts under `STUB_WORDS` words
+    pub stubs: Vec<PostLength>,
+    /// Posts with more words than this, more than three interquartile ranges
+    /// past the third quartile, are extreme outliers; `None` for fewer than
+    /// four posts
+    pub outlier_threshold: Option<f64>,
+    pub outliers: Vec<PostLength>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostLength {
+    pub title: String,
+    /// URL path of the post's page
+    pub path: String,
+    pub words: usize,
+}
+
+/// A post matching a search, from `Site::search`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub title: String,
+    pub date: chrono::NaiveDate,
+    /// Text around the first match, with matches between the given markers
+    pub snippet: String,
+}
+
+impl Site {
+    /// The site in `dir`, built to `output`, or if that's `None` to the
+    /// `output` its settings give. Fails if the settings can't be read or the
+    /// output directory would overlap the sources.
+    pub fn open(dir: impl Into<PathBuf>, output: Option<&Path>) -> Result<Site> {
+        let dir = dir.into();
+        let build_dir = build_dir(&dir, output)?;
+        Ok(Site {
+            dir,
+            build_dir,
+            before_build: vec![],
+            after_build: vec![],
+        })
+    }
+
+    /// Also runs `hook` before each build, after the `before_build` commands.
+    pub fn before_build(
+        mut self,
+        hook: impl Fn(&Site) -> Result<()> + Send + Sync + 'static,
+    ) -> Site {
+        self.before_build.push(Arc::new(hook));
+        self
+    }
+
+    /// Also runs `hook` after each build, after the `after_build` commands.
+    pub fn after_build(
+        mut self,
+        hook: impl Fn(&Site) -> Result<()> + Send + Sync + 'static,
+    ) -> Site {
+        self.after_build.push(Arc::new(hook));
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn build_dir(&self) -> &Path {
+        &self.build_dir
+    }
+
+    /// The site's settings, read afresh from its `stanley.toml`.
+    pub fn config(&self) -> Result<Config> {
+        Config::load(&self.dir)
+    }
+
+    /// Renders the site into the build directory, skipping outputs whose
+    /// inputs haven't changed since the last build unless `options.force`,
+    /// with the configured `[hooks]` and any `before_build` and
+    /// `after_build` functions run around it.
+    pub fn build(&self, options: &BuildOptions) -> Result<()> {
+        let hooks = self.config()?.hooks;
+        hooks::run(&hooks.before_build, &self.dir, &self.build_dir)?;
+        for hook in &self.before_build {
+            hook(self).context("before_build hook failed")?;
+        }
+        build(&self.dir, &self.build_dir, options)?;
+        hooks::run(&hooks.after_build, &self.dir, &self.build_dir)?;
+        for hook in &self.after_build {
+            hook(self).context("after_build hook failed")?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the build directory.
+    pub fn clean(&self) -> Result<()> {
+        clean(&self.build_dir)
+    }
+
+    /// Copies the build directory to wherever the site's `[deploy]` settings say.
+    pub fn publish(&self) -> Result<()> {
+        publish(&self.dir, &self.build_dir)
+    }
+
+    /// Sends the webmentions the last build found due, if the site sends them.
+    /// Call it only once that build is published, since what's sent is
+    /// recorded and not sent again.
+    pub fn send_webmentions(&self) -> Result<()> {
+        send_webmentions(&self.dir)
+    }
+
+    /// Adds the webmentions webmention.io has received since the last fetch
+    /// to the site's `webmentions.json`.
+    pub fn fetch_webmentions(&self) -> Result<()> {
+        webmentions::fetch(&self.dir)
+    }
+
+    /// Writes `posts/<slug>.md`, dated `today`, and returns its path.
+    /// Without an explicit `slug`, the slug is the date followed by the title.
+    pub fn new_post(
+        &self,
+        title_words: &[String],
+        slug: Option<&str>,
+        today: chrono::NaiveDate,
+    ) -> Result<PathBuf> {
+        new_post(&self.dir, title_words, slug, today)
+    }
+
+    /// Published posts matching `query`, best match first, with matches in
+    /// their snippets wrapped in `highlight`'s opening and closing markers.
+    pub fn search(&self, query: &str, highlight: (&str, &str)) -> Result<Vec<SearchResult>> {
+        search(&self.dir, query, highlight)
+    }
+
+    /// How long the published posts are.
+    pub fn lengths(&self) -> Result<LengthReport> {
+        lengths(&self.dir)
+    }
+
+    /// Serves the build directory on `localhost:port`. With `watch`, builds
+    /// first with those options, then rebuilds whenever the sources change
+    /// and reloads open pages. Never returns unless serving fails.
+    pub fn serve(&self, port: u16, watch: Option<&BuildOptions>) -> Result<()> {
+        let live_reload = match watch {
+            Some(options) => {
+                let options = BuildOptions {
+                    live_reload: true,
+                    ..options.clone()
+                };
+                self.build(&options)?;
+                let live_reload = Arc::new(serve::LiveReload::default());
+                let (site, reloader) = (self.clone(), live_reload.clone());
+                std::thread::spawn(move || {
+                    watch::watch(&site.dir, || match site.build(&options) {
+                        Ok(()) => {
+                            println!("Rebuilt {:?}", site.build_dir);
+                            reloader.reload();
+                        }
+                        Err(e) => eprintln!("Rebuild failed: {:#}", e),
+                    })
+                });
+                Some(live_reload)
+            }
+            None => None,
+        };
+        serve::serve(&self.build_dir, port, live_reload)
+    }
+}
+
+/// A post's full page, as a preview: without comments, related posts or
+/// links to its neighbours, and not encrypted even if it has a password.
+pub fn render_post(config: &Config, post: &Post) -> Result<String> {
+    Ok(crate::post(
+        config,
+        post,
+        &post.body,
+        None,
+        &[],
+        Neighbours {
+            older: None,
+            newer: None,
+        },
+    )?
+    .into_string())
+}
+
+/// A page from `pages/`, in the site's layout.
+pub fn render_page(config: &Config, page: &Page) -> Result<String> {
+    Ok(markdown_page(config, page)?.into_string())
+}
+
+/// Copies `build_dir` to wherever the site's `[deploy]` settings say.
+fn publish(site_dir: &Path, build_dir: &Path) -> Result<()> {
+    let config = Config::load(site_dir)?;
+    if config.has_preview_base_url() {
+        bail!("Set `base_url` in stanley.toml before deploying, so absolute URLs point at the site rather than {}", config.base_url);
+    }
+    let deploy = &config.deploy;
+    match (&deploy.rsync, &deploy.s3, &deploy.github_pages) {
+        (Some(target), None, None) => deploy::rsync(build_dir, target),
+        (None, Some(s3), None) => deploy::s3(build_dir, s3),
+        (None, None, Some(github_pages)) => {
+            deploy::github_pages(site_dir, build_dir, github_pages)
+        }
+        (None, None, None) => bail!(
+            "Nowhere to deploy to: set `rsync`, `s3` or `github_pages` under `[deploy]` in stanley.toml"
+        ),
+        _ => bail!("Set only one of `rsync`, `s3` and `github_pages` under `[deploy]`"),
+    }
+}
+
+/// Sends the webmentions the last build found due, if the site sends them.
+/// Only done by `deploy`, after publishing: the builds of `build` and
+/// `serve` aren't public yet, and sent mentions aren't retried.
+fn send_webmentions(site_dir: &Path) -> Result<()> {
+    if Config::load(site_dir)?.webmentions.send {
+        webmentions::send_due(&site_dir.join(".cache").join(webmentions::SENT))
+    } else {
+        Ok(())
+    }
+}
+
+/// Where to write the site: `--output` if given, otherwise the configured
+/// `output` relative to the site directory. Refuses directories that would
+/// mix generated files with sources, or that `clean` would delete sources with.
+fn build_dir(site_dir: &Path, output: Option<&Path>) -> Result<PathBuf> {
+    let build_dir = match output {
+        Some(output) => output.to_path_buf(),
+        None => site_dir.join(Config::load(site_dir)?.output),
+    };
+
+    let site = normalize(site_dir)?;
+    let build = normalize(&build_dir)?;
+    for sources in [site.join("posts"), site.join("pages"), site.join("static")] {
+        if build.starts_with(&sources) {
+            bail!(
+                "The output directory {:?} must not be inside {:?}",
+                build_dir,
+                sources
+            );
+        }
+    }
+    if site.starts_with(&build) {
+        bail!(
+            "The output directory {:?} must not contain the site directory {:?}",
+            build_dir,
+            site_dir
+        );
+    }
+
+    Ok(build_dir)
+}
+
+/// `path` made absolute, with `.` and `..` resolved without touching the
+/// filesystem, since the output directory may not exist yet.
+fn normalize(path: &Path) -> Result<PathBuf> {
+    let absolute =
+        std::path::absolute(path).with_context(|| format!("Could not resolve {:?}", path))?;
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            std::path::Component::CurDir => (),
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    Ok(normalized)
+}
+
+fn clean(build_dir: &Path) -> Result<()> {
+    if build_dir.exists() {
+        std::fs::remove_dir_all(build_dir)
+            .with_context(|| format!("Could not remove {:?}", build_dir))?;
+    }
+    Ok(())
+}
+
+/// The title, date and a snippet of every published post matching `query`.
+/// Locked posts are left out, since their bodies are secret.
+fn search(
+    site_dir: &Path,
+    query: &str,
+    (before, after): (&str, &str),
+) -> Result<Vec<SearchResult>> {
+    let config = Config::load(site_dir)?;
+    let plugins = plugins::Plugins::load(&site_dir.join("plugins"))?;
+    let today = chrono::Local::now().date_naive();
+    let (_, posts) = load_posts(&config, site_dir, &plugins, |_, post| {
+        !post.draft && post.created_on <= today && post.password.is_none()
+    })?;
+    let documents: Vec<search::Document> = posts
+        .into_iter()
+        .map(|post| search::Document {
+            title: post.title,
+            date: post.created_on,
+            text: post.text,
+        })
+        .collect();
+
+    Ok(search::search(&documents, query)
+        .into_iter()
+        .map(|hit| SearchResult {
+            title: hit.document.title.clone(),
+            date: hit.document.date,
+            snippet: search::snippet(&hit.document.text, query, before, after),
+        })
+        .collect())
+}
+
+fn lengths(site_dir: &Path) -> Result<LengthReport> {
+    let config = Config::load(site_dir)?;
+    let plugins = plugins::Plugins::load(&site_dir.join("plugins"))?;
+    let today = chrono::Local::now().date_naive();
+    let (_, posts) = load_posts(&config, site_dir, &plugins, |_, post| {
+        !post.draft && post.created_on <= today
+    })?;
+    Ok(length_report(&posts))
+}
+
+/// The posts in `site_dir` that `keep`, as the plugins' front matter
+/// transforms leave them, newest first with distinct slugs, and the
+/// source path of each.
+fn load_posts(
+    config: &Config,
+    site_dir: &Path,
+    plugins: &plugins::Plugins,
+    keep: impl Fn(&Path, &Post) -> bool + Sync,
+) -> Result<(Vec<PathBuf>, Vec<Post>)> {
+    let post_paths = get_markdown_files(&site_dir.join("posts"))
+        .with_context(|| "Could not get markdown files for posts")?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut sources = post_paths
+        .par_iter()
+        .map(|post_path| {
+            let content = std::fs::read_to_string(post_path)
+                .with_context(|| format!("Could not read post {:?}", post_path))?;
+            Ok((slug(post_path), content))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    plugins.transform_front_matter(&mut sources)?;
+
+    let parsed_posts = post_paths
+        .into_par_iter()
+        .zip(&sources)
+        .map(
+            |(post_path, (stem, content))| -> Result<Option<(PathBuf, Post)>> {
+                let post = parse_post(config, stem, content)
+                    .with_context(|| format!("Could not parse post {:?}", post_path))?;
+                Ok(keep(&post_path, &post).then_some((post_path, post)))
+            },
+        )
+        .collect::<Result<Vec<_>>>()?;
+    let mut posts: Vec<(PathBuf, Post)> = parsed_posts.into_iter().flatten().collect();
+
+    // ties are broken by source path so that deduplicated slugs are stable
+    posts.sort_unstable_by(|(a_path, a), (b_path, b)| {
+        b.created_on
+            .cmp(&a.created_on)
+            .then_with(|| b_path.cmp(a_path))
+    });
+    let (source_paths, mut posts): (Vec<PathBuf>, Vec<Post>) = posts.into_iter().unzip();
+    dedupe_slugs(config, &mut posts);
+    Ok((source_paths, posts))
+}
+
+fn length_report(posts: &[Post]) -> LengthReport {
+    let posts: Vec<&Post> = posts
+        .iter()
+        .filter(|post| post.password.is_none())
+        .collect();
+    let lengths: Vec<usize> = posts.iter().map(|post| post.words).collect();
+    let outlier_threshold = charts::extreme_outlier_threshold(&lengths);
+    let post_lengths = |keep: &dyn Fn(usize) -> bool| -> Vec<PostLength> {
+        posts
+            .iter()
+            .filter(|post| keep(post.words))
+            .map(|post| PostLength {
+                title: post.title.clone(),
+                path: post.path.clone(),
+                words: post.words,
+            })
+            .collect()
+    };
+    LengthReport {
+        buckets: charts::buckets(&lengths, 250),
+        stubs: post_lengths(&|words| words < STUB_WORDS),
+        outliers: outlier_threshold
+            .map(|threshold| post_lengths(&|words| words as f64 > threshold))
+            .unwrap_or_default(),
+        outlier_threshold,
+    }
+}
+
+/// Refuses slugs that aren't plain file names, which could write outside the build directory.
+fn check_slug(slug: &str) -> Result<()> {
+    if slug.is_empty() || slug.starts_with('.') || slug.contains(['/', '\\']) {
+        bail!("Invalid slug {:?}: it must be a plain file name", slug);
+    }
+    Ok(())
+}
+
+/// Gives posts that would share an output file distinct slugs by suffixing
+/// `-2`, `-3`, ... to all but the oldest, so existing URLs don't change when
+/// a newer post with the same title is added. `posts` is sorted newest first.
+fn dedupe_slugs(config: &Config, posts: &mut [Post]) {
+    let mut taken = std::collections::HashSet::new();
+    for post in posts.iter_mut().rev() {
+        let mut slug = post.slug.clone();
+        let mut n = 1;
+        while !taken.insert(slug.clone()) {
+            n += 1;
+            slug = format!("{}-{}", post.slug, n);
+        }
+        if slug != post.slug {
+            post.path = post_path(config, &slug, post.created_on);
+            post.permalink = format!("{}{}", config.base_url, post.path);
+            post.slug = slug;
+        }
+    }
+}
+
+/// A file name friendly version of a post title: lowercase ASCII letters
+/// and digits separated by dashes.
+fn title_slug(title: &str) -> String {
+    title
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Writes `posts/<slug>.md` with its front matter filled in, refusing to
+/// overwrite an existing file. Without an explicit `slug`, the slug is
+/// today's date followed by the title.
+fn new_post(
+    site_dir: &Path,
+    title_words: &[String],
+    slug: Option<&str>,
+    today: chrono::NaiveDate,
+) -> Result<PathBuf> {
+    let title = title_words.join(" ");
+    let today_dashed = today.format("%Y-%m-%d");
+    let explicit_slug = slug.is_some();
+    let slug = match slug {
+        Some(slug) => slug.to_string(),
+        None => format!("{}-{}", today_dashed, title_slug(&title)),
+    };
+    check_slug(&slug)?;
+    let path = site_dir.join("posts").join(format!("{}.md", slug));
+
+    // a JSON string is also a YAML string, quoted and escaped as needed
+    let mut post = format!(
+        "---\nlayout: post\ntitle: {}\ncreated: {}\n",
+        serde_json::to_string(&title)?,
+        today_dashed
+    );
+    if explicit_slug {
+        post.push_str(&format!("slug: {}\n", serde_json::to_string(&slug)?));
+    }
+    post.push_str("---\n\n\n");
+
+    std::fs::create_dir_all(site_dir.join("posts")).context("Could not create posts dir")?;
+
+    let mut file =
+        std::fs::File::create_new(&path).with_context(|| format!("Could not create {:?}", path))?;
+    file.write_all(post.as_bytes())
+        .with_context(|| format!("Could not write {:?}", path))?;
+
+    Ok(path)
+}
+
+fn build(site_dir: &Path, build_dir: &Path, options: &BuildOptions) -> Result<()> {
+    let mut config = Config::load(site_dir)?;
+    config.live_reload = options.live_reload;
+    config.minify = config.minify || options.minify;
+    config.templates =
+        templates::Templates::load(&config.layered_dirs(site_dir, "templates")?, &config)?;
+    let include_drafts = options.drafts || config.drafts;
+    if config.has_preview_base_url() && !options.live_reload {
+        eprintln!(
+            "Warning: `base_url` isn't set in stanley.toml, so the feed, sitemap, canonical \
+             links and other absolute URLs point at {}",
+            config.base_url
+        );
+    }
+    let today = chrono::Local::now().date_naive();
+    let manifest_path = site_dir.join(".cache").join("build-manifest.json");
+    let mut manifest = manifest::Manifest::load(&manifest_path);
+    let plugins = plugins::Plugins::load(&site_dir.join("plugins"))?;
+    // every output depends on the settings and on the code that renders it
+    let site_hash = manifest::hash(&[
+        env!("CARGO_PKG_VERSION").as_bytes(),
+        format!("{:?}", config).as_bytes(),
+        plugins.hash.as_bytes(),
+    ]);
+    std::fs::create_dir_all(build_dir).context("Could not create build dir")?;
+    // every file this build writes, or leaves in place because it's up to date
+    let mut outputs: BTreeSet<PathBuf> = BTreeSet::new();
+
+    std::fs::write(
+        build_dir.join(highlight::STYLESHEET),
+        highlight::stylesheet(),
+    )
+    .context("Could not write syntax highlighting stylesheet")?;
+    outputs.insert(build_dir.join(highlight::STYLESHEET));
+    // files copied as they are, rather than generated
+    let mut copied: BTreeSet<PathBuf> =
+        assets::copy_static(&config.layered_dirs(site_dir, "static")?, build_dir)
+            .context("Could not copy static files")?
+            .into_iter()
+            .collect();
+    outputs.extend(copied.iter().cloned());
+    let comments_cache_dir = site_dir.join(".cache").join("comments");
+    // the mentions already received are shown if webmention.io can't be reached
+    if config.webmentions.fetch && !options.live_reload {
+        if let Err(e) = webmentions::fetch(site_dir) {
+            eprintln!("Warning: could not fetch webmentions: {:#}", e);
+        }
+    }
+    let received_mentions = webmentions::Received::load(site_dir)?;
+
+    let (source_paths, mut posts) = load_posts(&config, site_dir, &plugins, |post_path, post| {
+        if post.draft && !include_drafts {
+            eprintln!("Skipping draft {:?}", post_path);
+            return false;
+        }
+        if post.created_on > today && !options.future {
+            eprintln!(
+                "Skipping {:?}, scheduled for {}",
+                post_path, post.created_on
+            );
+            return false;
+        }
+        true
+    })?;
+    for (post_path, post) in source_paths.iter().zip(&posts) {
+        if readability::too_hard(post.readability, config.readability_threshold) {
+            eprintln!(
+                "{:?} reads at grade level {:.1}, above the warning threshold of {:.1}",
+                post_path, post.readability, config.readability_threshold
+            );
+        }
+    }
+    let mut index_links = Vec::with_capacity(posts.len());
+
+    // asset directories sit next to the source, but are copied to the post's slug
+    posts
+        .par_iter_mut()
+        .zip(&source_paths)
+        .try_for_each(|(post, post_path)| -> Result<()> {
+            post.assets = assets::copy_post_assets(
+                &post_path.with_extension(""),
+                build_dir,
+                &post.slug,
+                &post.stylesheets,
+                &post.scripts,
+            )
+            .with_context(|| format!("Could not copy assets for {:?}", post_path))?;
+            Ok(())
+        })?;
+    let mut image_variants = BTreeSet::new();
+    for post in posts.iter_mut() {
+        let local_file =
+            |src: &str| urls::local_file(build_dir, &config.base_url, &post.permalink, src);
+        let (body, variants) = images::responsive(&post.body.0, &config.images, local_file);
+        post.body = PreEscaped(images::lazy_load(&body, local_file));
+        image_variants.extend(variants);
+    }
+    if let Some(settings) = &config.og_image {
+        let template = match &settings.template {
+            Some(template) => Some(
+                std::fs::read(site_dir.join(template))
+                    .with_context(|| format!("Could not read {:?}", template))?,
+            ),
+            None => None,
+        };
+        let cards_dir = site_dir.join(".cache").join("og");
+        std::fs::create_dir_all(&cards_dir)
+            .with_context(|| format!("Could not create {:?}", cards_dir))?;
+        std::fs::create_dir_all(build_dir.join("og")).context("Could not create og dir")?;
+        for post in posts.iter_mut() {
+            let card = og_image::card(&post.title, &config.title, settings, template.as_deref())
+                .into_string();
+            let card_path = cards_dir.join(format!("{}.svg", post.slug));
+            // only rewritten when it changes, so the PNG is only redrawn then
+            if std::fs::read_to_string(&card_path).ok().as_ref() != Some(&card) {
+                std::fs::write(&card_path, &card)
+                    .with_context(|| format!("Could not write {:?}", card_path))?;
+            }
+            let image_path = format!("/og/{}.png", post.slug);
+            image_variants.insert(images::Variant {
+                source: card_path,
+                path: output_file(build_dir, &image_path),
+                width: None,
+            });
+            post.og_image = Some(format!("{}{}", config.base_url, image_path));
+        }
+    }
+    images::convert(&config.images.command, &image_variants)?;
+    for variant in image_variants {
+        outputs.insert(variant.path.clone());
+        copied.insert(variant.path);
+    }
+    for post in &posts {
+        outputs.insert(output_file(build_dir, &post.path));
+        outputs.extend(post.assets.files.iter().cloned());
+        copied.extend(post.assets.files.iter().cloned());
+    }
+
+    if config.link_graph {
+        let mut link_graph = link_graph::LinkGraph::default();
+
+        for post in &posts {
+            link_graph.add_node(&post.slug, &post.title);
+            if post.password.is_some() {
+                continue;
+            }
+            for link in &post.links {
+                if let Some(target) = link_graph::internal_target(link, &config.base_url) {
+                    link_graph.add_edge(&post.slug, target);
+                }
+            }
+        }
+
+        std::fs::write(build_dir.join("links.dot"), link_graph.to_dot())
+            .context("Could not write link graph")?;
+        outputs.insert(build_dir.join("links.dot"));
+    }
+
+    if config.growth_page {
+        // locked posts' words are as secret as the posts
+        let unlocked = || posts.iter().filter(|post| post.password.is_none());
+        let growth_html = page(
+            &config,
+            "Growth",
+            &html! {
+                (charts::cumulative_chart(
+                    "Posts",
+                    &charts::running_totals(unlocked().map(|post| (post.created_on, 1))),
+                ))
+                (charts::cumulative_chart(
+                    "Words",
+                    &charts::running_totals(unlocked().map(|post| (post.created_on, post.words))),
+                ))
+            },
+        )?;
+        std::fs::write(build_dir.join("growth.html"), growth_html.into_string())
+            .context("Could not write growth page")?;
+        outputs.insert(build_dir.join("growth.html"));
+    }
+
+    if config.lengths_page {
+        let report = length_report(&posts);
+        let lengths_html = page(
+            &config,
+            "Post lengths",
+            &html! {
+                (charts::histogram("Posts by word count", &report.buckets))
+                h3 { "Stubs (under " (STUB_WORDS) " words)" }
+                ul {
+                    @for post in &report.stubs {
+                        li { a href=(post.path) { (PreEscaped(&post.title)) } " (" (post.words) " words)" }
+                    }
+                }
+                @if let Some(threshold) = report.outlier_threshold {
+                    h3 { "Extreme outliers (over " (threshold.round()) " words)" }
+                    ul {
+                        @for post in &report.outliers {
+                            li { a href=(post.path) { (PreEscaped(&post.title)) } " (" (post.words) " words)" }
+                        }
+                    }
+                }
+            },
+        )?;
+        std::fs::write(build_dir.join("lengths.html"), lengths_html.into_string())
+            .context("Could not write post lengths page")?;
+        outputs.insert(build_dir.join("lengths.html"));
+    }
+
+    let related_posts = search::related(
+        &posts
+            .iter()
+            .map(|post| search::Document {
+                title: post.title.clone(),
+                date: post.created_on,
+                text: post.text.clone(),
+            })
+            .collect::<Vec<_>>(),
+        config.related_posts,
+    );
+
+    // fetching comments, encrypting and rendering are done in parallel;
+    // only the writes happen one at a time
+    let rendered_posts = posts
+        .par_iter()
+        .zip(&related_posts)
+        .enumerate()
+        .map(
+            |(i, (post, related))| -> Result<Option<(PathBuf, String, String)>> {
+                let related: Vec<&Post> = related.iter().map(|&i| &posts[i]).collect();
+                let neighbours = Neighbours::of(&posts, i);
+                let mut sections = vec![];
+                // replies quote and discuss a post, so locked posts show none
+                if post.password.is_none() {
+                    let comment_sources: Vec<comments::Source> = post
+                        .mastodon
+                        .as_deref()
+                        .map(comments::Source::Mastodon)
+                        .into_iter()
+                        .chain(post.bluesky.as_deref().map(comments::Source::Bluesky))
+                        .collect();
+
+                    let file_comments =
+                        comments::from_files(&site_dir.join("comments").join(&post.slug))?;
+                    if !comment_sources.is_empty() || !file_comments.is_empty() {
+                        let comments = comments::comments_for(
+                            &comments_cache_dir,
+                            &post.slug,
+                            &comment_sources,
+                        );
+                        sections.push(comments_section(
+                            &comment_sources,
+                            &file_comments,
+                            &comments,
+                        ));
+                    }
+                    let mentions = received_mentions.of(&post.permalink);
+                    if !mentions.is_empty() {
+                        sections.push(webmentions_section(&mentions));
+                    }
+                    if let Some(embed) = config.comments.as_ref().filter(|_| post.comments) {
+                        sections.push(comments::embed(embed));
+                    }
+                }
+                let comments_html = (!sections.is_empty()).then(|| {
+                    html! {
+                        @for section in &sections {
+                            (section)
+                        }
+                    }
+                });
+
+                let post_output_path = output_file(build_dir, &post.path);
+
+                let inputs_hash = manifest::hash(&[
+                    site_hash.as_bytes(),
+                    post.source_hash.as_bytes(),
+                    // changes with the images it shows, too
+                    post.body.0.as_bytes(),
+                    post.assets.stylesheets.join("\n").as_bytes(),
+                    post.assets.scripts.join("\n").as_bytes(),
+                    comments_html.as_ref().map_or("", |c| &c.0).as_bytes(),
+                    related
+                        .iter()
+                        .chain(&neighbours.older)
+                        .chain(&neighbours.newer)
+                        .map(|related| format!("{}\n{}", related.slug, related.title))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                        .as_bytes(),
+                ]);
+
+                if !options.force && manifest.is_fresh(&post_output_path, &inputs_hash) {
+                    return Ok(None);
+                }
+
+                let body = match &post.password {
+                    Some(password) => encrypt::locked_body(
+                        &encrypt::encrypt(&post.body.0, password)
+                            .with_context(|| format!("Could not encrypt post {}", post.slug))?,
+                    ),
+                    None => post.body.clone(),
+                };
+
+                let post_layout_html = crate::post(
+                    &config,
+                    post,
+                    &body,
+                    comments_html.as_ref(),
+                    &related,
+                    neighbours,
+                )?;
+
+                Ok(Some((
+                    post_output_path,
+                    post_layout_html.into_string(),
+                    inputs_hash,
+                )))
+            },
+        )
+        .collect::<Result<Vec<_>>>()?;
+
+    let (mut rendered_pages, hashes): (Vec<(String, String)>, Vec<String>) = rendered_posts
+        .into_iter()
+        .flatten()
+        .map(|(path, html, hash)| ((path.to_string_lossy().into_owned(), html), hash))
+        .unzip();
+    plugins.transform_html(&mut rendered_pages)?;
+
+    for ((post_output_path, post_html), inputs_hash) in rendered_pages.into_iter().zip(hashes) {
+        let post_output_path = PathBuf::from(post_output_path);
+        if let Some(parent) = post_output_path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create {:?}", parent))?;
+        }
+        let mut post_output = std::fs::File::create(&post_output_path).with_context(|| {
+            format!("Could not create post output path: {:?}", &post_output_path)
+        })?;
+
+        post_output
+            .write_all(post_html.as_bytes())
+            .with_context(|| {
+                format!(
+                    "Could not write post output html to {:?}",
+                    &post_output_path
+                )
+            })?;
+
+        manifest.record(&post_output_path, inputs_hash);
+    }
+
+    for post in &posts {
+        let index_link_html = index_link(&config, post);
+
+        index_links.push(index_link_html);
+    }
+
+    if config.webmentions.send {
+        let published = posts
+            .iter()
+            .filter(|post| !post.draft && post.created_on <= today && post.password.is_none())
+            .map(|post| {
+                (
+                    post.permalink.clone(),
+                    (
+                        manifest::hash(&[post.body.0.as_bytes()]),
+                        webmentions::outbound_links(&post.body.0, &config.base_url),
+                    ),
+                )
+            })
+            .collect();
+        let sent_path = site_dir.join(".cache").join(webmentions::SENT);
+        let mut mentions = webmentions::Mentions::load(&sent_path);
+        mentions.update(published);
+        mentions.save(&sent_path)?;
+    }
+
+    match changelog::git_log(site_dir, &["posts/*.md", "pages/*.md"], CHANGELOG_ENTRIES) {
+        Ok(mut entries) => {
+            // skipped drafts have no page to link to
+            entries.retain(|entry| {
+                !entry.path.starts_with("posts/")
+                    || posts
+                        .iter()
+                        .any(|post| post.file_stem == slug(Path::new(&entry.path)))
+            });
+            let changelog_html = page(
+                &config,
+                "Changelog",
+                &html! {
+                    table {
+                        tbody {
+                            @for entry in &entries {
+                                @let stem = slug(Path::new(&entry.path));
+                                @let post = posts.iter().find(|post| post.file_stem == stem);
+                                @let href = post.map_or_else(|| page_path(&config, &stem), |post| post.path.clone());
+                                @let title = post.map_or(stem.as_str(), |post| post.title.as_str());
+                                tr {
+                                    td { (entry.date) }
+                                    td {
+                                        @match entry.change {
+                                            changelog::Change::Added => "New",
+                                            changelog::Change::Modified => "Updated",
+                                        }
+                                    }
+                                    td { a href=(href) { (PreEscaped(title)) } }
+                                }
+                            }
+                        }
+                    }
+                },
+            )?;
+            std::fs::write(
+                build_dir.join("changelog.html"),
+                changelog_html.into_string(),
+            )
+            .context("Could not write changelog page")?;
+            outputs.insert(build_dir.join("changelog.html"));
+        }
+        Err(e) => eprintln!("Skipping changelog: {:#}", e),
+    }
+
+    // slug -> (display name, posts), sorted by slug
+    let mut tags: std::collections::BTreeMap<String, (&str, Vec<&Post>)> = Default::default();
+    for post in &posts {
+        for tag in &post.tags {
+            let slug = tag_slug(tag);
+            if !slug.is_empty() {
+                tags.entry(slug).or_insert((tag, vec![])).1.push(post);
+            }
+        }
+    }
+
+    let tags_dir = build_dir.join("tags");
+    std::fs::create_dir_all(&tags_dir).context("Could not create tags dir")?;
+
+    for (tag, tagged_posts) in tags.values() {
+        let post_links: Vec<Markup> = tagged_posts
+            .iter()
+            .map(|post| index_link(&config, post))
+            .collect();
+        let tag_page_path = output_file(build_dir, &tag_path(&config, tag));
+        if let Some(parent) = tag_page_path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create {:?}", parent))?;
+        }
+        std::fs::write(
+            &tag_page_path,
+            tag_page(&config, tag, &post_links)?.into_string(),
+        )
+        .with_context(|| format!("Could not write tag page {:?}", tag_page_path))?;
+        outputs.insert(tag_page_path);
+    }
+
+    let tag_counts: Vec<(&str, usize)> = tags
+        .values()
+        .map(|(tag, tagged_posts)| (*tag, tagged_posts.len()))
+        .collect();
+    std::fs::write(
+        tags_dir.join("index.html"),
+        tags_index(&config, &tag_counts)?.into_string(),
+    )
+    .context("Could not write tags index")?;
+    outputs.insert(tags_dir.join("index.html"));
+
+    // posts are newest first, so years come out newest first too
+    let mut years: Vec<(i32, Vec<Markup>)> = vec![];
+    for (post, post_link) in posts.iter().zip(&index_links) {
+        let year = post.created_on.year();
+        match years.last_mut() {
+            Some((last_year, post_links)) if *last_year == year => {
+                post_links.push(post_link.clone())
+            }
+            _ => years.push((year, vec![post_link.clone()])),
+        }
+    }
+
+    std::fs::write(
+        build_dir.join("archive.html"),
+        archive(&config, &years)?.into_string(),
+    )
+    .context("Could not write archive")?;
+    outputs.insert(build_dir.join("archive.html"));
+
+    for (year, post_links) in &years {
+        let archive_year_path = output_file(build_dir, &archive_year_path(&config, *year));
+        if let Some(parent) = archive_year_path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create {:?}", parent))?;
+        }
+        std::fs::write(
+            &archive_year_path,
+            archive_year(&config, *year, post_links)?.into_string(),
+        )
+        .with_context(|| format!("Could not write archive page {:?}", archive_year_path))?;
+        outputs.insert(archive_year_path);
+    }
+
+    let posts_per_page = match config.posts_per_page {
+        0 => index_links.len().max(1),
+        n => n,
+    };
+    let index_pages: Vec<&[Markup]> = index_links.chunks(posts_per_page).collect();
+    let page_count = index_pages.len().max(1);
+
+    for page_number in 1..=page_count {
+        let post_links = index_pages
+            .get(page_number - 1)
+            .copied()
+            .unwrap_or_default();
+        let index_layout_html = index(&config, post_links, page_number, page_count)?;
+
+        let index_output_path = output_file(build_dir, &index_page_href(&config, page_number));
+        if let Some(parent) = index_output_path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create {:?}", parent))?;
+        }
+        std::fs::write(&index_output_path, index_layout_html.into_string())
+            .with_context(|| format!("Could not write index page {:?}", index_output_path))?;
+        outputs.insert(index_output_path);
+    }
+
+    let rss_feed_path = build_dir.join("feed");
+    std::fs::write(&rss_feed_path, feed(&config, &posts))
+        .with_context(|| format!("Could not write feed {:?}", rss_feed_path))?;
+    outputs.insert(rss_feed_path);
+
+    let search_index: Vec<search::IndexEntry> = posts
+        .iter()
+        .map(|post| search::IndexEntry {
+            title: &post.title,
+            url: post.path.clone(),
+            date: post.created_on,
+            text: &post.text,
+        })
+        .collect();
+    std::fs::write(
+        build_dir.join(search::INDEX),
+        search::index_json(&search_index)?,
+    )
+    .context("Could not write search index")?;
+    outputs.insert(build_dir.join(search::INDEX));
+    std::fs::write(
+        build_dir.join("search.html"),
+        search_page(&config)?.into_string(),
+    )
+    .context("Could not write search page")?;
+    outputs.insert(build_dir.join("search.html"));
+
+    let mut sitemap_urls = vec![(
+        format!("{}/", config.base_url),
+        posts.iter().map(Post::last_modified).max(),
+    )];
+    sitemap_urls.extend(
+        posts
+            .iter()
+            .filter(|post| post.password.is_none())
+            .map(|post| (post.permalink.clone(), Some(post.last_modified()))),
+    );
+
+    let page_sources = get_markdown_files(&site_dir.join("pages"))?;
+
+    for page_source in page_sources {
+        let pp = page_source?;
+        let contents =
+            std::fs::read_to_string(&pp).with_context(|| format!("Could not read {:?}", pp))?;
+        let mut page = parse_page(&config, &slug(&pp), &contents)
+            .with_context(|| format!("Could not parse page {:?}", pp))?;
+        let page_url = permalink(&config, &page.slug);
+        page.body = PreEscaped(images::lazy_load(&page.body.0, |src| {
+            urls::local_file(build_dir, &config.base_url, &page_url, src)
+        }));
+        if page.slug != NOT_FOUND_PAGE {
+            sitemap_urls.push((page_url, None));
+        }
+
+        let page_output_path = output_file(build_dir, &page_path(&config, &page.slug));
+        outputs.insert(page_output_path.clone());
+
+        let inputs_hash = manifest::hash(&[
+            site_hash.as_bytes(),
+            contents.as_bytes(),
+            page.body.0.as_bytes(),
+        ]);
+        if !options.force && manifest.is_fresh(&page_output_path, &inputs_hash) {
+            continue;
+        }
+
+        let mut rendered = [(
+            page_output_path.to_string_lossy().into_owned(),
+            markdown_page(&config, &page)?.into_string(),
+        )];
+        plugins.transform_html(&mut rendered)?;
+        let [(_, page_layout_html)] = rendered;
+
+        if let Some(parent) = page_output_path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create {:?}", parent))?;
+        }
+
+        let mut page_output = std::fs::File::create(&page_output_path)
+            .with_context(|| format!("Could not create {:?}", page_output_path))?;
+        page_output
+            .write_all(page_layout_html.as_bytes())
+            .with_context(|| format!("Could not write page to {:?}", page_output_path))?;
+
+        manifest.record(&page_output_path, inputs_hash);
+    }
+
+    let mut aliases = vec![];
+    for post in &posts {
+        for alias in &post.aliases {
+            let alias_output_path = output_file(build_dir, alias);
+            if !outputs.insert(alias_output_path.clone()) {
+                bail!(
+                    "Alias {:?} of {:?} would overwrite {:?}",
+                    alias,
+                    post.file_stem,
+                    alias_output_path
+                );
+            }
+            if let Some(parent) = alias_output_path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("Could not create {:?}", parent))?;
+            }
+            std::fs::write(
+                &alias_output_path,
+                redirects::stub(&post.permalink).into_string(),
+            )
+            .with_context(|| format!("Could not write redirect {:?}", alias_output_path))?;
+            aliases.push((alias.clone(), post.path.clone()));
+        }
+    }
+    if config.htaccess {
+        std::fs::write(build_dir.join(".htaccess"), redirects::htaccess(&aliases))
+            .context("Could not write .htaccess")?;
+        outputs.insert(build_dir.join(".htaccess"));
+    }
+    if config.netlify {
+        std::fs::write(build_dir.join("_redirects"), netlify::redirects(&aliases))
+            .context("Could not write _redirects")?;
+        outputs.insert(build_dir.join("_redirects"));
+        let mut headers = netlify::headers();
+        if config.activitypub.is_some() {
+            headers.push_str(&netlify::activitypub_headers());
+        }
+        std::fs::write(build_dir.join("_headers"), headers).context("Could not write _headers")?;
+        outputs.insert(build_dir.join("_headers"));
+    }
+
+    if let Some(settings) = &config.activitypub {
+        let public_key_pem = match &settings.public_key {
+            Some(path) => Some(
+                std::fs::read_to_string(site_dir.join(path))
+                    .with_context(|| format!("Could not read public key {:?}", path))?,
+            ),
+            None => None,
+        };
+        let contents: Vec<String> = posts
+            .iter()
+            .map(|post| urls::absolutize(&post.body.0, &post.permalink))
+            .collect();
+        let articles: Vec<activitypub::Article> = posts
+            .iter()
+            .zip(&contents)
+            .filter(|(post, _)| post.password.is_none())
+            .map(|(post, content)| activitypub::Article {
+                slug: &post.slug,
+                url: &post.permalink,
+                title: &post.title,
+                published: midnight_utc(post.created_on),
+                updated: post.updated_on.map(midnight_utc),
+                content,
+                tags: post
+                    .tags
+                    .iter()
+                    .map(|tag| {
+                        (
+                            tag.as_str(),
+                            format!("{}{}", config.base_url, tag_path(&config, tag)),
+                        )
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        let mut documents = vec![
+            (
+                build_dir.join(".well-known").join("webfinger"),
+                activitypub::webfinger(&config.base_url, settings),
+            ),
+            (
+                build_dir.join(activitypub::DIR).join("actor.json"),
+                activitypub::actor(
+                    &config.base_url,
+                    config.author.as_deref().unwrap_or(&config.title),
+                    &config.description,
+                    settings,
+                    public_key_pem.as_deref(),
+                ),
+            ),
+            (
+                build_dir.join(activitypub::DIR).join("outbox.json"),
+                activitypub::outbox(&config.base_url, &articles),
+            ),
+        ];
+        for article in &articles {
+            documents.push((
+                build_dir
+                    .join(activitypub::DIR)
+                    .join("posts")
+                    .join(format!("{}.json", article.slug)),
+                activitypub::article(&config.base_url, article),
+            ));
+        }
+        for (path, document) in documents {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("Could not create {:?}", parent))?;
+            }
+            std::fs::write(&path, serde_json::to_string_pretty(&document)?)
+                .with_context(|| format!("Could not write {:?}", path))?;
+            outputs.insert(path);
+        }
+    }
+
+    if !plugins.is_empty() {
+        let site = serde_json::json!({
+            "title": config.title,
+            "description": config.description,
+            "base_url": config.base_url,
+            "posts": posts
+                .iter()
+                .filter(|post| post.password.is_none())
+                .map(|post| serde_json::json!({
+                    "title": post.title,
+                    "slug": post.slug,
+                    "url": post.permalink,
+                    "created": post.created_on,
+                    "updated": post.updated_on,
+                    "tags": post.tags,
+                    "html": post.body.0,
+                }))
+                .collect::<Vec<_>>(),
+        });
+        for file in plugins.files(&site)? {
+            let path = build_dir.join(&file.path);
+            if !outputs.insert(path.clone()) {
+                bail!("A plugin's {:?} would overwrite {:?}", file.path, path);
+            }
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("Could not create {:?}", parent))?;
+            }
+            std::fs::write(&path, file.contents)
+                .with_context(|| format!("Could not write {:?}", path))?;
+        }
+    }
+
+    let not_found_path = build_dir.join("404.html");
+    if !outputs.contains(&not_found_path) {
+        std::fs::write(&not_found_path, not_found_page(&config)?.into_string())
+            .context("Could not write not-found page")?;
+        outputs.insert(not_found_path);
+    }
+
+    std::fs::write(
+        build_dir.join("sitemap.xml"),
+        sitemap::sitemap(&sitemap_urls),
+    )
+    .context("Could not write sitemap")?;
+    outputs.insert(build_dir.join("sitemap.xml"));
+
+    // each page's policy covers the inline styles and scripts it ended up
+    // with, whether from the layout, a template, or a plugin
+    outputs
+        .par_iter()
+        .filter(|output| {
+            output.extension().is_some_and(|ext| ext == "html") && !copied.contains(*output)
+        })
+        .try_for_each(|output| {
+            let html = std::fs::read_to_string(output)
+                .with_context(|| format!("Could not read {:?}", output))?;
+            let mut finished = csp::apply(&html);
+            if config.minify {
+                finished = minify::minify(&finished);
+            }
+            // already finished if it was left in place by an earlier build
+            if finished != html {
+                std::fs::write(output, finished)
+                    .with_context(|| format!("Could not write {:?}", output))?;
+            }
+            Ok::<(), anyhow::Error>(())
+        })?;
+
+    if config.precompress {
+        let compressible: Vec<PathBuf> = outputs
+            .iter()
+            .filter(|output| compress::is_compressible(output))
+            .cloned()
+            .collect();
+        let sizes = compress::compress_all(&compressible)?;
+        for output in &compressible {
+            outputs.insert(compress::gz_path(output));
+            outputs.insert(compress::br_path(output));
+        }
+        println!(
+            "Compressed {} files from {} KiB to {} KiB with gzip and {} KiB with brotli",
+            sizes.files,
+            sizes.original / 1024,
+            sizes.gzip / 1024,
+            sizes.brotli / 1024
+        );
+    }
+
+    manifest.remove_stale(build_dir, &outputs)?;
+    manifest.save(&manifest_path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn recognizes_a_post() {
+        let post_text = r"---
+layout: post
+title: some great title
+created: 2029-12-18
+---
+
+some incredible post body with
+multiple
+lines
+and paragraphs";
+
+        let p = crate::parse_post(&crate::Config::default(), "a-post", post_text).unwrap();
+
+        assert_eq!(p.title, "some great title");
+        assert_eq!(
+            p.created_on,
+            chrono::NaiveDate::parse_from_str("2029-12-18", "%Y-%m-%d").unwrap(),
+        );
+        assert_eq!(
+            p.body.0,
+            crate::md_to_html(
+                "some incredible post body with
+multiple
+lines
+and paragraphs",
+                crate::Config::default().markdown.options()
+            )
+            .0
+        )
+    }
+
+    #[test]
+    fn describes_posts_for_search_engines() {
+        let post_text = "---\ntitle: Ending </script> early\ncreated: 2024-05-06\nupdated: 2024-06-07\n---\n\nBody";
+        let config = crate::Config::default();
+        let post = crate::parse_post(&config, "a-post", post_text).unwrap();
+
+        let json_ld = crate::structured_data(&config, &post, "About things").into_string();
+        let json = json_ld
+            .strip_prefix("<script type=\"application/ld+json\">")
+            .and_then(|json| json.strip_suffix("</script>"))
+            .unwrap();
+        assert!(!json.contains("</"));
+        let posting: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(posting["headline"], "Ending </script> early");
+        assert_eq!(posting["datePublished"], "2024-05-06");
+        assert_eq!(posting["dateModified"], "2024-06-07");
+        assert_eq!(posting["author"]["name"], "stanley");
+    }
+
+    #[test]
+    fn marks_up_posts_with_microformats() {
+        let post_text = "---\ntitle: Hello\ncreated: 2024-05-06\ntags: rust\n---\n\nBody";
+        let config = crate::Config {
+            base_url: "https://example.com".to_string(),
+            author: Some("Clark".to_string()),
+            ..Default::default()
+        };
+        let post = crate::parse_post(&config, "a-post", post_text).unwrap();
+        let html = crate::post(
+            &config,
+            &post,
+            &maud::html! { p { "Body" } },
+            None,
+            &[],
+            crate::Neighbours {
+                older: None,
+                newer: None,
+            },
+        )
+        .unwrap()
+        .into_string();
+
+        assert!(html.contains("<div class=\"h-entry\"><h2 class=\"p-name\">Hello</h2>"));
+        assert!(html.contains(&format!("<a class=\"u-url\" href=\"{}\">", post.permalink)));
+        assert!(html.contains("<time class=\"dt-published\" datetime=\"2024-05-06\">"));
+        assert!(html.contains("<a class=\"p-category\" href=\"/tags/rust.html\">rust</a>"));
+        assert!(html.contains(
+            "<a class=\"p-author h-card\" href=\"https://example.com\" hidden>Clark</a>"
+        ));
+        assert!(html.contains("<div class=\"e-content\"><p>Body</p></div>"));
+    }
+
+    #[test]
+    fn recognizes_optional_post_fields() {
+        let post_text = r"---
+layout: post
+title: some great title
+created: 2029-12-18
+mastodon: https://mastodon.social/@someone/1234
+bluesky: at://did:plc:xyz/app.bsky.feed.post/3kabc
+css: [one.css, css/two.css]
+draft: true
+excerpt: a *short* summary
+slug: great
+updated: 2030-01-02
+---
+
+body";
+
+        let p = crate::parse_post(&crate::Config::default(), "a-post", post_text).unwrap();
+
+        assert_eq!(p.title, "some great title");
+        assert_eq!(p.slug, "great");
+        assert_eq!(
+            p.last_modified(),
+            chrono::NaiveDate::from_ymd_opt(2030, 1, 2).unwrap()
+        );
+        assert_eq!(
+            p.mastodon.as_deref(),
+            Some("https://mastodon.social/@someone/1234")
+        );
+        assert_eq!(
+            p.bluesky.as_deref(),
+            Some("at://did:plc:xyz/app.bsky.feed.post/3kabc")
+        );
+        assert_eq!(p.stylesheets, vec!["one.css", "css/two.css"]);
+        assert!(p.draft);
+        assert_eq!(
+            p.excerpt.map(|excerpt| excerpt.0),
+            Some("<p>a <em>short</em> summary</p>\n".to_string())
+        );
+        assert_eq!(
+            p.body.0,
+            crate::md_to_html("body", crate::Config::default().markdown.options()).0
+        );
+
+        let p = crate::parse_post(
+            &crate::Config::default(),
+            "a-post",
+            "---\ntitle: t\ncreated: 2029-12-18\n---\nbody",
+        )
+        .unwrap();
+        assert_eq!(p.mastodon, None);
+        assert_eq!(p.bluesky, None);
+        assert!(p.stylesheets.is_empty());
+        assert!(!p.draft);
+        assert!(p.excerpt.is_none());
+    }
+
+    #[test]
+    fn counts_words_and_reading_time() {
+        let post_text = "---\ntitle: t\ncreated: 2029-12-18\n---\n\nsome incredible post body with\nmultiple\nlines\nand paragraphs";
+        let p = crate::parse_post(&crate::Config::default(), "a-post", post_text).unwrap();
+        assert_eq!(p.words, 9);
+        assert_eq!(p.reading_minutes(), 1);
+    }
+
+    #[test]
+    fn takes_the_excerpt_up_to_the_more_marker() {
+        let post_text = "---\ntitle: t\ncreated: 2029-12-18\n---\nintro\n\n<!-- more -->\n\nrest";
+        let p = crate::parse_post(&crate::Config::default(), "a-post", post_text).unwrap();
+        assert_eq!(p.excerpt.unwrap().0, "<p>intro</p>\n");
+        assert!(p.body.0.contains("rest"));
+
+        let locked = post_text.replace("created:", "password: hunter2\ncreated:");
+        let p = crate::parse_post(&crate::Config::default(), "a-post", &locked).unwrap();
+        assert!(p.excerpt.is_none());
+    }
+
+    #[test]
+    fn feed_summaries_leave_out_the_body() {
+        let mut config = crate::Config::default();
+        config.feed.summary = true;
+        let words = vec!["word"; 100].join(" ");
+        let post_text = format!("---\ntitle: t\ncreated: 2029-12-18\n---\n{}\n", words);
+        let post = crate::parse_post(&config, "t", &post_text).unwrap();
+
+        let item = crate::rss_item(&config, &post);
+        assert!(item.content().is_none());
+        let description = item.description().unwrap();
+        assert!(description.starts_with("<p>word word"));
+        assert!(description.ends_with("word…</p>"));
+        assert_eq!(description.matches("word").count(), crate::SUMMARY_WORDS);
+
+        config.feed.summary = false;
+        assert!(crate::rss_item(&config, &post).content().is_some());
+    }
+
+    #[test]
+    fn failing_hooks_fail_the_build() {
+        let dir = crate::test_dir::TestDir::new("site-hooks");
+        std::fs::write(
+            dir.join("stanley.toml"),
+            "[hooks]\nbefore_build = [\"touch before\"]\n",
+        )
+        .unwrap();
+
+        let site = crate::Site::open(dir.to_path_buf(), None)
+            .unwrap()
+            .before_build(|_| anyhow::bail!("no"));
+        let error = site.build(&crate::BuildOptions::default()).unwrap_err();
+        assert_eq!(error.to_string(), "before_build hook failed");
+        assert!(dir.join("before").exists());
+        assert!(!site.build_dir().exists());
+    }
+
+    #[test]
+    fn policies_allow_template_styles() {
+        use base64::Engine;
+        use sha2::Digest;
+
+        let dir = crate::test_dir::TestDir::new("site-csp");
+        std::fs::create_dir_all(dir.join("templates")).unwrap();
+        std::fs::write(
+            dir.join("templates/layout.html"),
+            "<html><head>{{ head | safe }}<style>p{}</style></head><body>{{ content | safe }}</body></html>",
+        )
+        .unwrap();
+
+        let site = crate::Site::open(dir.to_path_buf(), None).unwrap();
+        site.build(&crate::BuildOptions::default()).unwrap();
+        let html = std::fs::read_to_string(site.build_dir().join("index.html")).unwrap();
+        let hash = base64::engine::general_purpose::STANDARD.encode(sha2::Sha256::digest("p{}"));
+        assert!(
+            html.contains(&format!("style-src 'self' 'sha256-{}';", hash)),
+            "{}",
+            html
+        );
+    }
+
+    #[test]
+    fn pretty_urls_cover_listing_pages() {
+        let dir = crate::test_dir::TestDir::new("site-pretty");
+        std::fs::create_dir_all(dir.join("posts")).unwrap();
+        std::fs::write(
+            dir.join("stanley.toml"),
+            "pretty_urls = true\nposts_per_page = 1\n",
+        )
+        .unwrap();
+        for (slug, created) in [("one", "2023-01-01"), ("two", "2024-01-01")] {
+            std::fs::write(
+                dir.join("posts").join(format!("{}.md", slug)),
+                format!(
+                    "---\ntitle: {}\ncreated: {}\ntags: [Rust]\n---\nHi\n",
+                    slug, created
+                ),
+            )
+            .unwrap();
+        }
+
+        let site = crate::Site::open(dir.to_path_buf(), None).unwrap();
+        site.build(&crate::BuildOptions::default()).unwrap();
+        let build_dir = site.build_dir();
+        let index = std::fs::read_to_string(build_dir.join("index.html")).unwrap();
+        assert!(index.contains("href=\"/tags/rust/\""), "{}", index);
+        assert!(
+            index.contains("href=\"/page/2/\" rel=\"next\""),
+            "{}",
+            index
+        );
+        let archive = std::fs::read_to_string(build_dir.join("archive.html")).unwrap();
+        assert!(archive.contains("href=\"/archive/2023/\""), "{}", archive);
+        for page in ["tags/rust", "page/2", "archive/2023"] {
+            assert!(
+                build_dir.join(page).join("index.html").is_file(),
+                "{}",
+                page
+            );
+        }
+    }
+
+    #[test]
+    fn searches_posts_as_plugins_leave_them() {
+        let dir = crate::test_dir::TestDir::new("site-search");
+        std::fs::create_dir_all(dir.join("posts")).unwrap();
+        std::fs::create_dir_all(dir.join("plugins")).unwrap();
+        std::fs::write(
+            dir.join("posts/a-post.md"),
+            "---\ntitle: Original\ncreated: 2024-01-01\n---\nSome words\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("plugins/rename.wasm"),
+            crate::plugins::tests::responder(
+                r#"{"posts": [{"front_matter": {"title": "Renamed", "created": "2024-01-01"}}]}"#,
+            ),
+        )
+        .unwrap();
+
+        let site = crate::Site::open(dir.to_path_buf(), None).unwrap();
+        let results = site.search("words", ("[", "]")).unwrap();
+        let titles: Vec<&str> = results.iter().map(|result| result.title.as_str()).collect();
+        assert_eq!(titles, ["Renamed"]);
+        assert_eq!(results[0].snippet, "Some [words]");
+    }
+
+    #[test]
+    fn feeds_leave_out_locked_posts() {
+        let mut config = crate::Config::default();
+        config.feed.limit = 1;
+        let posts: Vec<crate::Post> = [
+            "---\ntitle: Locked\ncreated: 2024-05-01\npassword: hunter2\n---\nSecret\n",
+            "---\ntitle: Newer\ncreated: 2024-02-01\nupdated: 2024-04-01\n---\nBody\n",
+            "---\ntitle: Older\ncreated: 2024-01-01\n---\nBody\n",
+        ]
+        .iter()
+        .map(|text| crate::parse_post(&config, "post", text).unwrap())
+        .collect();
+
+        let channel = rss::Channel::read_from(crate::feed(&config, &posts).as_bytes()).unwrap();
+        let titles: Vec<_> = channel.items().iter().map(|item| item.title()).collect();
+        assert_eq!(titles, [Some("Newer")]);
+        assert_eq!(
+            channel.last_build_date(),
+            Some("Mon, 1 Apr 2024 00:00:00 +0000")
+        );
+    }
+
+    #[test]
+    fn fills_in_permalink_patterns() {
+        let mut config = crate::Config::default();
+        let created = chrono::NaiveDate::from_ymd_opt(2024, 3, 9).unwrap();
+        let build_dir = std::path::Path::new("build");
+
+        let path = crate::post_path(&config, "hello", created);
+        assert_eq!(path, "/hello.html");
+        assert_eq!(
+            crate::output_file(build_dir, &path),
+            build_dir.join("hello.html")
+        );
+
+        config.permalink = "/:year/:month/:day/:slug/".to_string();
+        let path = crate::post_path(&config, "hello", created);
+        assert_eq!(path, "/2024/03/09/hello/");
+        assert_eq!(
+            crate::output_file(build_dir, &path),
+            build_dir.join("2024/03/09/hello/index.html")
+        );
+
+        config.permalink = "/:year/:slug.html".to_string();
+        config.pretty_urls = true;
+        assert_eq!(crate::post_path(&config, "hello", created), "/2024/hello/");
+        assert_eq!(crate::page_path(&config, "about"), "/about/");
+        assert_eq!(
+            crate::output_file(build_dir, &crate::page_path(&config, "about")),
+            build_dir.join("about/index.html")
+        );
+        assert_eq!(crate::page_path(&config, "404"), "/404.html");
+    }
+
+    #[test]
+    fn pages_have_no_inline_style_attributes() {
+        // the Content-Security-Policy only allows the hashed <style> element
+        let config = crate::Config::default();
+        let post = crate::parse_post(
+            &config,
+            "hello",
+            "---\ntitle: Hello\ncreated: 2024-01-01\ntags: [a]\n---\nHi\n",
+        )
+        .unwrap();
+        let links = [crate::index_link(&config, &post)];
+        for page in [
+            crate::render_post(&config, &post).unwrap(),
+            crate::index(&config, &links, 1, 2).unwrap().into_string(),
+            crate::archive(&config, &[(2024, links.to_vec())])
+                .unwrap()
+                .into_string(),
+            crate::archive_year(&config, 2024, &links)
+                .unwrap()
+                .into_string(),
+            crate::tag_page(&config, "a", &links).unwrap().into_string(),
+        ] {
+            assert!(!page.contains("style=\""), "{}", page);
+        }
+    }
+
+    #[test]
+    fn only_links_comments_to_web_urls() {
+        let file_comment = crate::comments::FileComment {
+            id: "1".to_string(),
+            author: "Eve".to_string(),
+            author_url: Some("javascript:alert(1)".to_string()),
+            date: chrono::NaiveDate::from_ymd_opt(2024, 5, 7).unwrap(),
+            body: maud::html! {},
+        };
+        let comment = crate::comments::Comment {
+            author: "Mallory".to_string(),
+            author_url: "data:text/html,hi".to_string(),
+            url: "https://example.social/@m/1".to_string(),
+            created_at: "2024-05-07".to_string(),
+            text: "Hi".to_string(),
+        };
+        let html = crate::comments_section(&[], &[file_comment], &[comment]).into_string();
+        assert!(!html.contains("javascript:"), "{}", html);
+        assert!(!html.contains("data:"), "{}", html);
+        assert!(html.contains("<a href=\"https://example.social/@m/1\">2024-05-07</a>"));
+    }
+
+    #[test]
+    fn only_links_mentions_to_web_urls() {
+        assert_eq!(
+            crate::mention_link("https://a.example/", "Ann").into_string(),
+            "<a href=\"https://a.example/\">Ann</a>"
+        );
+        assert_eq!(
+            crate::mention_link("javascript:alert(1)", "Eve").into_string(),
+            "Eve"
+        );
+    }
+
+    #[test]
+    fn reports_stubs_and_outliers() {
+        let config = crate::Config::default();
+        let post = |title: &str, words: usize, password: &str| {
+            let text = format!(
+                "---\ntitle: {}\ncreated: 2024-01-01\n{}---\n{}\n",
+                title,
+                password,
+                "word ".repeat(words)
+            );
+            crate::parse_post(&config, "x", &text).unwrap()
+        };
+        let posts = [
+            post("Stub", 10, ""),
+            post("A", 300, ""),
+            post("B", 310, ""),
+            post("C", 320, ""),
+            post("Epic", 5000, ""),
+            post("Locked", 1, "password: hunter2\n"),
+        ];
+        let report = crate::length_report(&posts);
+        let titles = |posts: &[crate::PostLength]| -> Vec<String> {
+            posts.iter().map(|post| post.title.clone()).collect()
+        };
+        assert_eq!(titles(&report.stubs), ["Stub"]);
+        assert_eq!(titles(&report.outliers), ["Epic"]);
+        assert_eq!(report.buckets[0], ("0-249".to_string(), 1));
+        assert_eq!(report.buckets[1], ("250-499".to_string(), 3));
+    }
+
+    #[test]
+    fn locked_posts_hide_their_length() {
+        let config = crate::Config::default();
+        let source = |password: &str| {
+            format!(
+                "---\ntitle: Hello\ncreated: 2024-01-01\n{}---\nSome secret words\n",
+                password
+            )
+        };
+        let open = crate::parse_post(&config, "open", &source("")).unwrap();
+        let locked = crate::parse_post(&config, "locked", &source("password: hunter2\n")).unwrap();
+        assert!(crate::render_post(&config, &open)
+            .unwrap()
+            .contains("3 words, 1 min read"));
+        assert!(!crate::render_post(&config, &locked)
+            .unwrap()
+            .contains("min read"));
+    }
+
+    #[test]
+    fn locked_posts_link_no_activitypub_object() {
+        let config = crate::Config {
+            activitypub: Some(crate::config::ActivityPub::default()),
+            ..crate::Config::default()
+        };
+        let source = |password: &str| {
+            format!(
+                "---\ntitle: Hello\ncreated: 2024-01-01\n{}---\nHi\n",
+                password
+            )
+        };
+        let open = crate::parse_post(&config, "open", &source("")).unwrap();
+        let locked = crate::parse_post(&config, "locked", &source("password: hunter2\n")).unwrap();
+        let alternate = "type=\"application/activity+json\"";
+        assert!(crate::render_post(&config, &open)
+            .unwrap()
+            .contains(alternate));
+        assert!(!crate::render_post(&config, &locked)
+            .unwrap()
+            .contains(alternate));
+    }
+
+    #[test]
+    fn gives_posts_distinct_slugs() {
+        let config = crate::Config::default();
+        let post = |title: &str, created: &str| {
+            let text = format!("---\ntitle: \"{}\"\ncreated: {}\n---\n", title, created);
+            crate::parse_post(&config, "file-stem", &text).unwrap()
+        };
+        let mut posts = [
+            post("Week notes", "2024-01-15"),
+            post("日本語", "2024-01-10"),
+            post("Week notes!", "2024-01-08"),
+            post("Week notes", "2024-01-01"),
+        ];
+        crate::dedupe_slugs(&config, &mut posts);
+
+        let slugs: Vec<&str> = posts.iter().map(|post| post.slug.as_str()).collect();
+        assert_eq!(
+            slugs,
+            ["week-notes-3", "file-stem", "week-notes-2", "week-notes"]
+        );
+        assert_eq!(
+            posts[0].permalink,
+            "http://localhost:8080/week-notes-3.html"
+        );
+        assert!(crate::parse_post(
+            &config,
+            "x",
+            "---\ntitle: t\ncreated: 2024-01-01\nslug: ../t\n---\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn new_post_refuses_to_overwrite() {
+        let site_dir = crate::test_dir::TestDir::new("new");
+        let today = chrono::NaiveDate::parse_from_str("2029-12-18", "%Y-%m-%d").unwrap();
+        let title = vec!["Some:".to_string(), "title".to_string()];
+
+        let path = crate::new_post(&site_dir, &title, None, today).unwrap();
+        let post = crate::parse_post(
+            &crate::Config::default(),
+            "2029-12-18-some-title",
+            &std::fs::read_to_string(&path).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(path, site_dir.join("posts/2029-12-18-some-title.md"));
+        assert_eq!(post.title, "Some: title");
+        assert!(crate::new_post(&site_dir, &title, None, today).is_err());
+
+        let path = crate::new_post(&site_dir, &title, Some("custom"), today).unwrap();
+        assert_eq!(path, site_dir.join("posts/custom.md"));
+        let post = crate::parse_post(
+            &crate::Config::default(),
+            "custom",
+            &std::fs::read_to_string(&path).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(post.slug, "custom");
+        assert!(crate::new_post(&site_dir, &title, Some("../escape"), today).is_err());
+    }
+
+    #[test]
+    fn renders_enabled_markdown_extensions() {
+        let markdown = "| a |\n|---|\n| b |\n\n~~gone~~";
+
+        let enabled = crate::md_to_html(markdown, crate::Config::default().markdown.options());
+        assert!(enabled.0.contains("<table>"));
+        assert!(enabled.0.contains("<del>gone</del>"));
+
+        let disabled = crate::md_to_html(markdown, crate::Options::empty());
+        assert!(!disabled.0.contains("<table>"));
+    }
+
+    #[test]
+    fn output_must_not_overlap_sources() {
+        let site_dir = crate::test_dir::TestDir::new("output");
+        let out = |output: &str| crate::build_dir(&site_dir, Some(&site_dir.join(output)));
+
+        assert_eq!(out("build").unwrap(), site_dir.join("build"));
+        assert_eq!(out("../elsewhere").unwrap(), site_dir.join("../elsewhere"));
+        assert!(out("posts/build").is_err());
+        assert!(out("build/../pages").is_err());
+        assert!(out("static").is_err());
+        assert!(out(".").is_err());
+        assert!(out("..").is_err());
+    }
+
+    #[test]
+    fn slugifies_tags() {
+        assert_eq!(crate::tag_slug("Rust"), "rust");
+        assert_eq!(crate::tag_slug(" web  dev/css "), "web-dev-css");
+    }
+
+    #[test]
+    fn reply_mailto_encodes_subject_and_body() {
+        assert_eq!(
+            crate::reply_mailto(
+                "me@example.com",
+                "Rust & you",
+                "https://example.com/a-post.html"
+            ),
+            "mailto:me@example.com?subject=Re%3A%20Rust%20%26%20you&body=https%3A%2F%2Fexample.com%2Fa-post.html"
+        );
+    }
+
+    #[test]
+    fn recognizes_a_page() {
+        let page_text = r"---
+title: some great title
+---
+
+some incredible page body with
+multiple
+lines
+and paragraphs";
+
+        let p = crate::parse_page(&crate::Config::default(), "a-page", page_text).unwrap();
+
+        assert_eq!(p.title, "some great title");
+        assert_eq!(
+            p.body.0,
+            crate::md_to_html(
+                "some incredible page body with
+multiple
+lines
+and paragraphs",
+                crate::Config::default().markdown.options()
+            )
+            .0
+        )
+    }
+}
@@ -0,0 +1,125 @@
+use pulldown_cmark::{Event, Parser, Tag};
+
+/// Every link destination in a markdown document, in order of appearance.
+pub(crate) fn links(markdown_str: &str) -> Vec<String> {
+    Parser::new(markdown_str)
+        .filter_map(|event| match event {
+            Event::Start(Tag::Link { dest_url, .. }) => Some(dest_url.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// If `link` points at a page on this site, the file stem of the page it
+/// points at, e.g. `2023-01-02-hello.html#intro` -> `2023-01-02-hello`,
+/// or its directory for pretty URLs, e.g. `/2023/01/hello/` -> `hello`.
+pub(crate) fn internal_target<'a>(link: &'a str, base_url: &str) -> Option<&'a str> {
+    let link = link.split('#').next().unwrap_or(link);
+    let link = link.strip_prefix(base_url).unwrap_or(link);
+
+    if link.contains("://") || link.starts_with("mailto:") {
+        return None;
+    }
+
+    let link = link.strip_suffix("index.html").unwrap_or(link);
+    match link.strip_suffix('/') {
+        Some(dir) => dir.rsplit('/').next(),
+        None => link.rsplit('/').next()?.strip_suffix(".html"),
+    }
+    .filter(|stem| !stem.is_empty())
+}
+
+/// Posts as nodes and internal links between them as edges.
+#[derive(Default)]
+pub(crate) struct LinkGraph {
+    /// (file stem, title)
+    nodes: Vec<(String, String)>,
+    /// (from file stem, to file stem)
+    edges: Vec<(String, String)>,
+}
+
+impl LinkGraph {
+    pub(crate) fn add_node(&mut self, stem: &str, title: &str) {
+        self.nodes.push((stem.to_string(), title.to_string()));
+    }
+
+    pub(crate) fn add_edge(&mut self, from: &str, to: &str) {
+        self.edges.push((from.to_string(), to.to_string()));
+    }
+
+    /// Graphviz DOT, dropping edges that point at pages that aren't nodes.
+    pub(crate) fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph links {\n");
+
+        for (stem, title) in &self.nodes {
+            dot.push_str(&format!(
+                "    \"{}\" [label=\"{}\"];\n",
+                escape(stem),
+                escape(title)
+            ));
+        }
+
+        for (from, to) in &self.edges {
+            if self.nodes.iter().any(|(stem, _)| stem == to) {
+                dot.push_str(&format!(
+                    "    \"{}\" -> \"{}\";\n",
+                    escape(from),
+                    escape(to)
+                ));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn finds_internal_targets() {
+        let base_url = "https://example.com";
+
+        assert_eq!(
+            super::internal_target("2023-01-02-hello.html#intro", base_url),
+            Some("2023-01-02-hello")
+        );
+        assert_eq!(
+            super::internal_target("https://example.com/about.html", base_url),
+            Some("about")
+        );
+        assert_eq!(
+            super::internal_target("https://elsewhere.com/about.html", base_url),
+            None
+        );
+        assert_eq!(
+            super::internal_target("/2023/01/hello/", base_url),
+            Some("hello")
+        );
+        assert_eq!(super::internal_target("img/cat.png", base_url), None);
+        assert_eq!(super::internal_target("/", base_url), None);
+    }
+
+    #[test]
+    fn renders_dot_without_dangling_edges() {
+        let mut graph = super::LinkGraph::default();
+        graph.add_node("a", "Post \"A\"");
+        graph.add_node("b", "Post B");
+        graph.add_edge("a", "b");
+        graph.add_edge("b", "missing");
+
+        assert_eq!(
+            graph.to_dot(),
+            "digraph links {
+    \"a\" [label=\"Post \\\"A\\\"\"];
+    \"b\" [label=\"Post B\"];
+    \"a\" -> \"b\";
+}
+"
+        );
+    }
+}
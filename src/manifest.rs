@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// Hashes of the inputs each output file was last rendered from,
+/// so outputs whose inputs haven't changed can be left alone,
+/// and the list of files the last build produced, so outputs whose
+/// source has gone away can be removed.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct Manifest {
+    /// Output path -> inputs hash
+    outputs: BTreeMap<String, String>,
+    /// Every file the last build wrote or kept, relative to the build directory
+    #[serde(default)]
+    files: BTreeSet<PathBuf>,
+}
+
+impl Manifest {
+    /// Reads the manifest at `path`. A missing or unreadable manifest is
+    /// treated as empty, which just means everything gets rebuilt.
+    pub(crate) fn load(path: &Path) -> Manifest {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create {:?}", parent))?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Could not write build manifest {:?}", path))
+    }
+
+    /// Whether `output` exists and was last rendered from inputs hashing to `hash`.
+    pub(crate) fn is_fresh(&self, output: &Path, hash: &str) -> bool {
+        output.is_file()
+            && self
+                .outputs
+                .get(output.to_string_lossy().as_ref())
+                .is_some_and(|recorded| recorded == hash)
+    }
+
+    pub(crate) fn record(&mut self, output: &Path, hash: String) {
+        self.outputs
+            .insert(output.to_string_lossy().into_owned(), hash);
+    }
+
+    /// Deletes the files the last build produced that this build, which
+    /// produced `files`, didn't, along with any directories that leaves empty.
+    /// Files stanley never wrote are left alone.
+    pub(crate) fn remove_stale(
+        &mut self,
+        build_dir: &Path,
+        files: &BTreeSet<PathBuf>,
+    ) -> Result<()> {
+        let files: BTreeSet<PathBuf> = files
+            .iter()
+            .filter_map(|file| file.strip_prefix(build_dir).ok())
+            .map(Path::to_path_buf)
+            .collect();
+
+        for stale in self.files.difference(&files) {
+            let path = build_dir.join(stale);
+            if path.is_file() {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Could not remove stale output {:?}", path))?;
+                println!("Removed stale output {:?}", path);
+            }
+            self.outputs.remove(path.to_string_lossy().as_ref());
+
+            // fails, and so stops, at the first directory that isn't empty
+            for dir in path.ancestors().skip(1) {
+                if dir == build_dir || std::fs::remove_dir(dir).is_err() {
+                    break;
+                }
+            }
+        }
+
+        self.files = files;
+        Ok(())
+    }
+}
+
+/// A hex SHA-256 over several inputs, each length-prefixed so that
+/// moving bytes from one input to the next changes the hash.
+pub(crate) fn hash(inputs: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for input in inputs {
+        hasher.update((input.len() as u64).to_le_bytes());
+        hasher.update(input);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+
+    #[test]
+    fn outputs_are_fresh_only_when_their_inputs_match() {
+        let dir = crate::test_dir::TestDir::new("manifest");
+        let output = dir.join("a-post.html");
+        let manifest_path = dir.join("manifest.json");
+
+        let hash = super::hash(&[b"ab", b"c"]);
+        assert_ne!(hash, super::hash(&[b"a", b"bc"]));
+
+        let mut manifest = Manifest::default();
+        manifest.record(&output, hash.clone());
+        assert!(!manifest.is_fresh(&output, &hash), "output not written yet");
+
+        std::fs::write(&output, "<html>").unwrap();
+        manifest.save(&manifest_path).unwrap();
+        let manifest = Manifest::load(&manifest_path);
+        assert!(manifest.is_fresh(&output, &hash));
+        assert!(!manifest.is_fresh(&output, &super::hash(&[b"abc"])));
+    }
+
+    #[test]
+    fn removes_only_outputs_that_are_no_longer_built() {
+        let build_dir = crate::test_dir::TestDir::new("stale");
+        std::fs::create_dir_all(build_dir.join("old-post")).unwrap();
+        for file in ["kept.html", "old-post.html", "old-post/cat.png", "CNAME"] {
+            std::fs::write(build_dir.join(file), "").unwrap();
+        }
+
+        let mut manifest = Manifest::default();
+        let first_build = ["kept.html", "old-post.html", "old-post/cat.png"]
+            .map(|file| build_dir.join(file))
+            .into();
+        manifest.remove_stale(&build_dir, &first_build).unwrap();
+
+        let second_build = [build_dir.join("kept.html")].into();
+        manifest.remove_stale(&build_dir, &second_build).unwrap();
+
+        assert!(build_dir.join("kept.html").exists());
+        assert!(build_dir.join("CNAME").exists(), "never written by stanley");
+        assert!(!build_dir.join("old-post.html").exists());
+        assert!(!build_dir.join("old-post").exists());
+    }
+}
@@ -0,0 +1,46 @@
+use crate::config::Config;
+use maud::{html, Markup, PreEscaped};
+use pulldown_cmark::{Event, Options, Parser};
+
+/// Typesets every `.math` span that pulldown-cmark emits for `$...$` and
+/// `$$...$$` with KaTeX, once the deferred KaTeX script has loaded.
+pub(crate) const RENDER_SCRIPT: &str = r#"document.addEventListener("DOMContentLoaded", () => {
+  for (const element of document.querySelectorAll(".math")) {
+    katex.render(element.textContent, element, {
+      displayMode: element.classList.contains("math-display"),
+      throwOnError: false,
+    });
+  }
+});"#;
+
+/// Whether `markdown_str` has any math in it, so KaTeX is only loaded where it's needed.
+pub(crate) fn has_math(markdown_str: &str, options: Options) -> bool {
+    options.contains(Options::ENABLE_MATH)
+        && Parser::new_ext(markdown_str, options)
+            .any(|event| matches!(event, Event::InlineMath(_) | Event::DisplayMath(_)))
+}
+
+/// The KaTeX stylesheet and script, served from the site itself so the
+/// Content-Security-Policy allows them, and the script that runs KaTeX.
+pub(crate) fn head(config: &Config) -> Markup {
+    let katex = config.markdown.katex.trim_end_matches('/');
+    html! {
+        link rel="stylesheet" href=(format!("{}/katex.min.css", katex)) type="text/css";
+        script src=(format!("{}/katex.min.js", katex)) defer {}
+        script { (PreEscaped(RENDER_SCRIPT)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::Options;
+
+    #[test]
+    fn finds_math_only_when_enabled() {
+        let options = Options::ENABLE_MATH;
+        assert!(super::has_math("Euler: $e^{i\\pi} + 1 = 0$", options));
+        assert!(super::has_math("$$\n\\int_0^1 x\\,dx\n$$", options));
+        assert!(!super::has_math("It costs $5.", options));
+        assert!(!super::has_math("$x$", Options::empty()));
+    }
+}
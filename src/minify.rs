@@ -0,0 +1,82 @@
+/// Elements whose contents are copied exactly, because whitespace in them
+/// matters or, for scripts and styles, because their hashes are in the
+/// Content-Security-Policy.
+const RAW_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/// `html` with comments removed and each run of whitespace collapsed to a
+/// single space, except within the `RAW_ELEMENTS`.
+pub(crate) fn minify(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+            continue;
+        }
+
+        if let Some(element) = raw_element(rest) {
+            let close = format!("</{}", element);
+            let end = find_ignoring_case(&rest[1..], &close).map_or(rest.len(), |i| i + 1);
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+            // the closing tag itself is ordinary markup
+            if let Some(gt) = rest.find('>') {
+                out.push_str(&rest[..=gt]);
+                rest = &rest[gt + 1..];
+            }
+            continue;
+        }
+
+        let c = rest.chars().next().expect("rest is not empty");
+        if c.is_ascii_whitespace() {
+            // a removed comment may have had whitespace on both sides
+            if !out.ends_with(' ') {
+                out.push(' ');
+            }
+            rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+        } else {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    out
+}
+
+/// The name of the raw element whose opening tag `html` starts with, if any.
+fn raw_element(html: &str) -> Option<&'static str> {
+    let tag = html.strip_prefix('<')?;
+    RAW_ELEMENTS.into_iter().find(|element| {
+        tag.get(..element.len())
+            .is_some_and(|name| name.eq_ignore_ascii_case(element))
+            && tag[element.len()..].starts_with(|c: char| c == '>' || c.is_ascii_whitespace())
+    })
+}
+
+fn find_ignoring_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn collapses_whitespace_outside_raw_elements() {
+        let html = "<html>\n  <body>\n    <!-- more -->\n    <p>Some   text,\n    wrapped.</p>\n\
+                    <pre><code>fn main() {\n    x\n}</code></pre>\n\
+                    <SCRIPT>if (a  <  b) {\n}</SCRIPT>\n  </body>\n</html>\n";
+        assert_eq!(
+            super::minify(html),
+            "<html> <body> <p>Some text, wrapped.</p> \
+             <pre><code>fn main() {\n    x\n}</code></pre> \
+             <SCRIPT>if (a  <  b) {\n}</SCRIPT> </body> </html> "
+        );
+        assert_eq!(
+            super::minify("<preview>  a</preview>"),
+            "<preview> a</preview>"
+        );
+    }
+}
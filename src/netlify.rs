@@ -0,0 +1,50 @@
+/// A Netlify `_redirects` file permanently redirecting each `(from, to)` pair.
+pub(crate) fn redirects(redirects: &[(String, String)]) -> String {
+    redirects
+        .iter()
+        .map(|(from, to)| format!("{} {} 301\n", from, to))
+        .collect()
+}
+
+/// A Netlify `_headers` file sending the security headers with every
+/// response. Each page's own policy is in its `<meta>` tag, so the header
+/// policy only forbids framing the site, which a `<meta>` tag can't.
+pub(crate) fn headers() -> String {
+    "/*\n  \
+     Content-Security-Policy: frame-ancestors 'none'\n  \
+     X-Frame-Options: DENY\n  \
+     X-Content-Type-Options: nosniff\n  \
+     Referrer-Policy: strict-origin-when-cross-origin\n"
+        .to_string()
+}
+
+/// `_headers` rules serving the ActivityPub documents with the content
+/// types fediverse servers expect, to any origin.
+pub(crate) fn activitypub_headers() -> String {
+    format!(
+        "/{}/*\n  \
+         Content-Type: application/activity+json\n  \
+         Access-Control-Allow-Origin: *\n\
+         /.well-known/webfinger\n  \
+         Content-Type: application/jrd+json\n  \
+         Access-Control-Allow-Origin: *\n",
+        crate::activitypub::DIR
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn writes_redirects_and_headers() {
+        assert_eq!(
+            super::redirects(&[("/old/".to_string(), "/new.html".to_string())]),
+            "/old/ /new.html 301\n"
+        );
+        assert_eq!(
+            super::headers(),
+            "/*\n  Content-Security-Policy: frame-ancestors 'none'\n  X-Frame-Options: DENY\n  X-Content-Type-Options: nosniff\n  Referrer-Policy: strict-origin-when-cross-origin\n"
+        );
+        assert!(super::activitypub_headers()
+            .starts_with("/ap/*\n  Content-Type: application/activity+json\n"));
+    }
+}
@@ -0,0 +1,87 @@
+use crate::config;
+use base64::Engine;
+use maud::{html, Markup};
+
+/// The size Facebook, Mastodon and others show link previews at.
+pub(crate) const WIDTH: u32 = 1200;
+pub(crate) const HEIGHT: u32 = 630;
+const PADDING: u32 = 80;
+const TITLE_SIZE: u32 = 64;
+/// Roughly how many characters of the title fit on a line at `TITLE_SIZE`.
+const LINE_CHARS: usize = 30;
+const MAX_LINES: usize = 4;
+
+/// A social preview card for a post, as SVG: `title` in large type over
+/// `template`, a PNG, or the configured background color, with `site_name`
+/// below it.
+pub(crate) fn card(
+    title: &str,
+    site_name: &str,
+    settings: &config::OgImage,
+    template: Option<&[u8]>,
+) -> Markup {
+    let lines = wrap(title, LINE_CHARS, MAX_LINES);
+    let line_height = TITLE_SIZE * 5 / 4;
+    html! {
+        svg xmlns="http://www.w3.org/2000/svg" width=(WIDTH) height=(HEIGHT) viewBox=(format!("0 0 {} {}", WIDTH, HEIGHT)) {
+            rect width=(WIDTH) height=(HEIGHT) fill=(settings.background) {}
+            @if let Some(template) = template {
+                image width=(WIDTH) height=(HEIGHT) href=(format!(
+                    "data:image/png;base64,{}",
+                    base64::engine::general_purpose::STANDARD.encode(template)
+                )) {}
+            }
+            text x=(PADDING) y=(PADDING + TITLE_SIZE) font-family="sans-serif" font-size=(TITLE_SIZE) font-weight="bold" fill=(settings.color) {
+                @for (i, line) in lines.iter().enumerate() {
+                    tspan x=(PADDING) dy=(if i == 0 { 0 } else { line_height }) { (line) }
+                }
+            }
+            text x=(PADDING) y=(HEIGHT - PADDING) font-family="sans-serif" font-size=(TITLE_SIZE / 2) fill=(settings.color) {
+                (site_name)
+            }
+        }
+    }
+}
+
+/// `text` broken into lines of at most about `width` characters, at spaces,
+/// with an ellipsis on the last if it takes more than `max_lines`.
+fn wrap(text: &str, width: usize, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= width => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        if let Some(last) = lines.last_mut() {
+            last.push('…');
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn wraps_the_title_onto_a_card() {
+        assert_eq!(
+            super::wrap("A rather long title that needs a few lines", 12, 3),
+            ["A rather", "long title", "that needs a…"]
+        );
+
+        let card = super::card(
+            "Fish & chips",
+            "Clark Kampfe",
+            &crate::config::OgImage::default(),
+            None,
+        )
+        .into_string();
+        assert!(card.contains("<tspan x=\"80\" dy=\"0\">Fish &amp; chips</tspan>"));
+        assert!(card.contains(">Clark Kampfe</text>"));
+    }
+}
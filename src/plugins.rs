@@ -0,0 +1,398 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Component, Path, PathBuf};
+use wasmtime::{Engine, Linker, Module, Store, Trap};
+use wasmtime_wasi::p1::{self, WasiP1Ctx};
+use wasmtime_wasi::p2::pipe::{MemoryInputPipe, MemoryOutputPipe};
+use wasmtime_wasi::{I32Exit, WasiCtxBuilder};
+
+/// The most a plugin may write to stdout, or to stderr, in one run.
+const MAX_OUTPUT: usize = 64 * 1024 * 1024;
+
+/// Fuel each run of a plugin gets, roughly one unit per instruction: some
+/// seconds of work, so a plugin stuck in a loop fails the build instead of
+/// hanging it.
+const FUEL: u64 = 10_000_000_000;
+
+/// WebAssembly modules in a site's `plugins/` directory, run in turn at each
+/// stage of the build as WASI commands. Each reads a JSON request on stdin,
+/// `{"hook": ..., ...}`, and writes a JSON response to stdout; a response
+/// without the hook's field leaves things as they were. Plugins get no
+/// arguments, environment or files: only the request.
+#[derive(Debug, Default)]
+pub(crate) struct Plugins {
+    /// Compiled, sorted by file name, which is the order they run in
+    modules: Vec<(PathBuf, Module)>,
+    /// Hash of every module, so changing one rebuilds everything
+    pub(crate) hash: String,
+    /// Fuel each run gets, `FUEL` but for tests
+    fuel: u64,
+}
+
+/// A file a plugin adds to the build, relative to the build directory.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub(crate) struct File {
+    pub(crate) path: String,
+    pub(crate) contents: String,
+}
+
+impl Plugins {
+    /// Compiles the `*.wasm` modules in `dir`, if it exists.
+    pub(crate) fn load(dir: &Path) -> Result<Plugins> {
+        if !dir.is_dir() {
+            return Ok(Plugins {
+                fuel: FUEL,
+                ..Plugins::default()
+            });
+        }
+        let mut paths = vec![];
+        for entry in std::fs::read_dir(dir).with_context(|| format!("Could not read {:?}", dir))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "wasm") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let binaries = paths
+            .iter()
+            .map(|path| std::fs::read(path).with_context(|| format!("Could not read {:?}", path)))
+            .collect::<Result<Vec<_>>>()?;
+        let engine = Engine::new(wasmtime::Config::new().consume_fuel(true))
+            .map_err(|e| anyhow!("Could not set up the plugin runtime: {:?}", e))?;
+        let modules = paths
+            .into_iter()
+            .zip(&binaries)
+            .map(|(path, binary)| {
+                let module = Module::from_binary(&engine, binary)
+                    .map_err(|e| anyhow!("Could not compile plugin {:?}: {:?}", path, e))?;
+                Ok((path, module))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Plugins {
+            modules,
+            hash: crate::manifest::hash(&binaries.iter().map(Vec::as_slice).collect::<Vec<_>>()),
+            fuel: FUEL,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+
+    /// Lets each plugin rewrite the front matter of `posts`, (file stem,
+    /// source) pairs, whose sources are replaced with the result. Plugins
+    /// see neither the password nor the body of a locked post.
+    pub(crate) fn transform_front_matter(&self, posts: &mut [(String, String)]) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let (mut documents, secrets): (Vec<Value>, Vec<(Option<Value>, String)>) = posts
+            .iter()
+            .map(|(stem, source)| document(stem, source))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+
+        for plugin in &self.modules {
+            let request = json!({ "hook": "front_matter", "posts": documents });
+            if let Some(transformed) = self.call(plugin, &request, "posts", documents.len())? {
+                for (document, transformed) in documents.iter_mut().zip(transformed) {
+                    if let Some(front_matter) = transformed.get("front_matter") {
+                        document["front_matter"] = front_matter.clone();
+                    }
+                }
+            }
+        }
+
+        for ((_, source), (document, (password, body))) in
+            posts.iter_mut().zip(documents.into_iter().zip(secrets))
+        {
+            let mut front_matter = document["front_matter"].clone();
+            if let (Some(front_matter), Some(password)) = (front_matter.as_object_mut(), password) {
+                front_matter.insert("password".to_string(), password);
+            }
+            *source = format!(
+                "---\n{}---\n{}",
+                serde_yaml::to_string(&front_matter)?,
+                body
+            );
+        }
+        Ok(())
+    }
+
+    /// Lets each plugin rewrite the HTML of `pages`, (path under the build
+    /// directory, HTML) pairs, in place.
+    pub(crate) fn transform_html(&self, pages: &mut [(String, String)]) -> Result<()> {
+        for plugin in &self.modules {
+            let documents: Vec<Value> = pages
+                .iter()
+                .map(|(path, html)| json!({ "path": path, "html": html }))
+                .collect();
+            let request = json!({ "hook": "html", "pages": documents });
+            if let Some(transformed) = self.call(plugin, &request, "pages", pages.len())? {
+                for ((_, html), transformed) in pages.iter_mut().zip(transformed) {
+                    if let Some(transformed) = transformed.get("html").and_then(Value::as_str) {
+                        *html = transformed.to_string();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The files the plugins add to the build, given `site`, a description of
+    /// the site and its posts.
+    pub(crate) fn files(&self, site: &Value) -> Result<Vec<File>> {
+        let mut files = vec![];
+        for plugin @ (path, _) in &self.modules {
+            let request = json!({ "hook": "files", "site": site });
+            let response = run(plugin, &request, self.fuel)?;
+            if let Some(added) = response.get("files") {
+                let added: Vec<File> = serde_json::from_value(added.clone())
+                    .with_context(|| format!("Plugin {:?} returned invalid files", path))?;
+                for file in &added {
+                    if !is_relative_path(&file.path) {
+                        bail!(
+                            "Plugin {:?} tried to write {:?}, outside the build directory",
+                            path,
+                            file.path
+                        );
+                    }
+                }
+                files.extend(added);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Runs `plugin` on `request`, returning the `field` array of its
+    /// response, which must have `len` items, if it has one.
+    fn call(
+        &self,
+        plugin: &(PathBuf, Module),
+        request: &Value,
+        field: &str,
+        len: usize,
+    ) -> Result<Option<Vec<Value>>> {
+        let response = run(plugin, request, self.fuel)?;
+        let Some(items) = response.get(field) else {
+            return Ok(None);
+        };
+        match items.as_array() {
+            Some(items) if items.len() == len => Ok(Some(items.clone())),
+            _ => bail!(
+                "Plugin {:?} must return `{}` as an array of {} items",
+                plugin.0,
+                field,
+                len
+            ),
+        }
+    }
+}
+
+/// What plugins see of the post `stem` with `source`, and what they don't:
+/// its password, if it has one, and its body.
+fn document(stem: &str, source: &str) -> Result<(Value, (Option<Value>, String))> {
+    let (front_matter, body) = crate::front_matter::split(source)
+        .with_context(|| format!("Could not parse post {:?}", stem))?;
+    let mut front_matter: Value = serde_yaml::from_str(front_matter)
+        .with_context(|| format!("Invalid front matter in post {:?}", stem))?;
+    let password = front_matter
+        .as_object_mut()
+        .and_then(|front_matter| front_matter.remove("password"));
+    // a locked post's body is as secret as its password
+    let shown_body = if password.is_some() { None } else { Some(body) };
+    Ok((
+        json!({ "slug": stem, "front_matter": front_matter, "body": shown_body }),
+        (password, body.to_string()),
+    ))
+}
+
+/// Runs `module`'s `_start` with `request` on stdin and `fuel` to spend,
+/// and parses its stdout.
+fn run((path, module): &(PathBuf, Module), request: &Value, fuel: u64) -> Result<Value> {
+    let stdout = MemoryOutputPipe::new(MAX_OUTPUT);
+    let stderr = MemoryOutputPipe::new(MAX_OUTPUT);
+    let wasi = WasiCtxBuilder::new()
+        .stdin(MemoryInputPipe::new(serde_json::to_vec(request)?))
+        .stdout(stdout.clone())
+        .stderr(stderr.clone())
+        .build_p1();
+    let mut store = Store::new(module.engine(), wasi);
+    store
+        .set_fuel(fuel)
+        .map_err(|e| anyhow!("Could not fuel plugin {:?}: {:?}", path, e))?;
+    let mut linker: Linker<WasiP1Ctx> = Linker::new(module.engine());
+    p1::add_to_linker_sync(&mut linker, |wasi| wasi)
+        .map_err(|e| anyhow!("Could not set up WASI: {:?}", e))?;
+
+    let result = linker
+        .instantiate(&mut store, module)
+        .and_then(|instance| instance.get_typed_func::<(), ()>(&mut store, "_start"))
+        .and_then(|start| start.call(&mut store, ()));
+    if let Err(e) = result {
+        if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+            bail!(
+                "Plugin {:?} timed out: it ran out of fuel after {} instructions",
+                path,
+                fuel
+            );
+        }
+        // a command that calls `exit(0)` ends with a trap too
+        if !matches!(e.downcast_ref::<I32Exit>(), Some(I32Exit(0))) {
+            bail!(
+                "Plugin {:?} failed: {:?}\n{}",
+                path,
+                e,
+                String::from_utf8_lossy(&stderr.contents())
+            );
+        }
+    }
+    serde_json::from_slice(&stdout.contents())
+        .with_context(|| format!("Plugin {:?} did not respond with JSON", path))
+}
+
+/// Whether `path` is a plain relative path that stays below where it's joined to.
+fn is_relative_path(path: &str) -> bool {
+    let path = Path::new(path);
+    !path.as_os_str().is_empty()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::path::PathBuf;
+
+    /// A WASI command that ignores its request and responds with `json`.
+    pub(crate) fn responder(json: &str) -> Vec<u8> {
+        wat::parse_str(format!(
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "{}")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const {}))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+            json.bytes()
+                .map(|byte| format!("\\{:02x}", byte))
+                .collect::<String>(),
+            json.len()
+        ))
+        .unwrap()
+    }
+
+    fn plugins(dir: &std::path::Path, modules: &[(&str, Vec<u8>)]) -> super::Plugins {
+        for (name, module) in modules {
+            std::fs::write(dir.join(name), module).unwrap();
+        }
+        super::Plugins::load(dir).unwrap()
+    }
+
+    #[test]
+    fn plugins_transform_front_matter_and_html_and_add_files() {
+        let dir = crate::test_dir::TestDir::new("plugins");
+        // each hook only reads its own field of the response
+        let plugins = plugins(
+            &dir,
+            &[
+                (
+                    "1-everything.wasm",
+                    responder(
+                        r#"{"posts": [{"front_matter": {"title": "Tagged", "tags": ["plugin"]}}],
+                            "pages": [{"html": "<p>replaced</p>"}],
+                            "files": [{"path": "humans.txt", "contents": "hi"}]}"#,
+                    ),
+                ),
+                ("2-nothing.wasm", responder("{}")),
+                ("notes.txt", b"not a plugin".to_vec()),
+            ],
+        );
+        assert_eq!(plugins.modules.len(), 2);
+
+        let mut posts = [(
+            "a-post".to_string(),
+            "---\ntitle: Original\n---\nBody\n".to_string(),
+        )];
+        plugins.transform_front_matter(&mut posts).unwrap();
+        assert_eq!(
+            posts[0].1,
+            "---\ntags:\n- plugin\ntitle: Tagged\n---\nBody\n"
+        );
+
+        // the password survives a plugin replacing the front matter
+        let mut locked = [(
+            "secret".to_string(),
+            "---\ntitle: Original\npassword: hunter2\n---\nHidden\n".to_string(),
+        )];
+        plugins.transform_front_matter(&mut locked).unwrap();
+        assert_eq!(
+            locked[0].1,
+            "---\npassword: hunter2\ntags:\n- plugin\ntitle: Tagged\n---\nHidden\n"
+        );
+        let (shown, _) = super::document("secret", &locked[0].1).unwrap();
+        assert_eq!(shown["front_matter"].get("password"), None);
+        assert_eq!(shown["body"], serde_json::Value::Null);
+
+        let mut pages = [("a-post.html".to_string(), "<p>original</p>".to_string())];
+        plugins.transform_html(&mut pages).unwrap();
+        assert_eq!(pages[0].1, "<p>replaced</p>");
+
+        let files = plugins.files(&serde_json::json!({})).unwrap();
+        assert_eq!(
+            files,
+            [super::File {
+                path: "humans.txt".to_string(),
+                contents: "hi".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn plugins_cannot_write_outside_the_build_directory() {
+        let dir = crate::test_dir::TestDir::new("plugins-out");
+        let plugins = plugins(
+            &dir,
+            &[(
+                "escape.wasm",
+                responder(r#"{"files": [{"path": "../x", "contents": ""}]}"#),
+            )],
+        );
+        assert!(plugins.files(&serde_json::json!({})).is_err());
+        assert!(super::is_relative_path("a/b.txt"));
+        assert!(!super::is_relative_path("/etc/passwd"));
+
+        assert!(super::Plugins::load(&PathBuf::from("missing"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn failing_plugins_fail_the_build() {
+        let dir = crate::test_dir::TestDir::new("plugins-trap");
+        let trap = wat::parse_str(r#"(module (func (export "_start") unreachable))"#).unwrap();
+        let plugins = plugins(&dir, &[("trap.wasm", trap)]);
+        let error = plugins.files(&serde_json::json!({})).unwrap_err();
+        assert!(error.to_string().contains("trap.wasm"), "{}", error);
+    }
+
+    #[test]
+    fn plugins_that_run_forever_time_out() {
+        let dir = crate::test_dir::TestDir::new("plugins-loop");
+        let spin = wat::parse_str(r#"(module (func (export "_start") (loop br 0)))"#).unwrap();
+        let mut plugins = plugins(&dir, &[("spin.wasm", spin)]);
+        plugins.fuel = 1_000_000;
+        let error = plugins.files(&serde_json::json!({})).unwrap_err();
+        assert!(
+            error.to_string().starts_with("Plugin ")
+                && error.to_string().contains("spin.wasm\" timed out"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,93 @@
+/// Posts scoring above this Flesch-Kincaid grade level get a warning during
+/// the build, unless the site sets its own `readability_threshold`.
+pub(crate) const WARN_ABOVE_GRADE: f64 = 14.0;
+
+/// Whether a post reading at `grade` deserves a warning under `threshold`,
+/// where a threshold of 0 turns the warnings off.
+pub(crate) fn too_hard(grade: f64, threshold: f64) -> bool {
+    threshold > 0.0 && grade > threshold
+}
+
+/// Flesch-Kincaid grade level of `text`: roughly the number of years of
+/// schooling needed to follow it comfortably.
+pub(crate) fn flesch_kincaid_grade(text: &str) -> f64 {
+    let words: Vec<&str> = text
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .collect();
+
+    if words.is_empty() {
+        return 0.0;
+    }
+
+    let sentences = text
+        .split(['.', '!', '?'])
+        .filter(|sentence| sentence.chars().any(char::is_alphanumeric))
+        .count()
+        .max(1);
+
+    let syllables: usize = words.iter().map(|word| syllables(word)).sum();
+
+    let words_per_sentence = words.len() as f64 / sentences as f64;
+    let syllables_per_word = syllables as f64 / words.len() as f64;
+
+    0.39 * words_per_sentence + 11.8 * syllables_per_word - 15.59
+}
+
+/// Approximates syllables by counting vowel groups, ignoring a silent trailing "e".
+fn syllables(word: &str) -> usize {
+    let word: Vec<char> = word
+        .chars()
+        .filter(char::is_ascii_alphabetic)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    let is_vowel = |c: &char| matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y');
+
+    let mut count = 0;
+    let mut previous_was_vowel = false;
+    for c in &word {
+        let vowel = is_vowel(c);
+        if vowel && !previous_was_vowel {
+            count += 1;
+        }
+        previous_was_vowel = vowel;
+    }
+
+    if count > 1 && word.ends_with(&['e']) && !word.ends_with(&['l', 'e']) {
+        count -= 1;
+    }
+
+    count.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn counts_syllables() {
+        assert_eq!(super::syllables("cat"), 1);
+        assert_eq!(super::syllables("make"), 1);
+        assert_eq!(super::syllables("table"), 2);
+        assert_eq!(super::syllables("readability"), 5);
+    }
+
+    #[test]
+    fn simple_text_scores_lower_than_dense_text() {
+        let simple = super::flesch_kincaid_grade("The cat sat. The dog ran. We had fun.");
+        let dense = super::flesch_kincaid_grade(
+            "Incremental compilation fundamentally necessitates sophisticated \
+             dependency tracking infrastructure, particularly considering \
+             heterogeneous intermediate representations.",
+        );
+
+        assert!(simple < 2.0);
+        assert!(dense > super::WARN_ABOVE_GRADE);
+    }
+
+    #[test]
+    fn a_zero_threshold_turns_warnings_off() {
+        assert!(super::too_hard(16.0, 14.0));
+        assert!(!super::too_hard(12.0, 14.0));
+        assert!(!super::too_hard(16.0, 0.0));
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::{bail, Result};
+use maud::{html, Markup, DOCTYPE};
+
+/// `alias` from front matter as a URL path, accepting it with or without
+/// the leading `/`. Rejects anything that would be written outside the
+/// build directory.
+pub(crate) fn alias_path(alias: &str) -> Result<String> {
+    let path = format!("/{}", alias.trim_start_matches('/'));
+    if path == "/" || path.split('/').any(|segment| segment == "..") || path.contains('\\') {
+        bail!(
+            "Invalid alias {:?}: it must be a path within the site",
+            alias
+        );
+    }
+    Ok(path)
+}
+
+/// A page that sends browsers on to `url` immediately, and tells search
+/// engines that `url` is the page to index.
+pub(crate) fn stub(url: &str) -> Markup {
+    html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="utf-8";
+                title { "Redirecting…" }
+                link rel="canonical" href=(url);
+                meta name="robots" content="noindex";
+                meta http-equiv="refresh" content=(format!("0; url={}", url));
+            }
+            body {
+                p { "This page has moved to " a href=(url) { (url) } "." }
+            }
+        }
+    }
+}
+
+/// An Apache `.htaccess` permanently redirecting each `(from, to)` pair,
+/// for servers that honor it, rather than relying on the stub pages.
+pub(crate) fn htaccess(redirects: &[(String, String)]) -> String {
+    redirects
+        .iter()
+        .map(|(from, to)| format!("Redirect 301 {} {}\n", from, to))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn writes_redirects_for_aliases() {
+        assert_eq!(super::alias_path("old.html").unwrap(), "/old.html");
+        assert_eq!(super::alias_path("/2020/old/").unwrap(), "/2020/old/");
+        assert!(super::alias_path("../etc/passwd").is_err());
+        assert!(super::alias_path("/").is_err());
+
+        let stub = super::stub("https://example.com/new.html").into_string();
+        assert!(stub.contains(
+            "<meta http-equiv=\"refresh\" content=\"0; url=https://example.com/new.html\">"
+        ));
+
+        assert_eq!(
+            super::htaccess(&[("/old.html".to_string(), "/new.html".to_string())]),
+            "Redirect 301 /old.html /new.html\n"
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use maud::{html, Markup, PreEscaped};
+use serde::Serialize;
+
+/// How strongly repeated occurrences of a term raise a document's score (Okapi BM25's `k1`).
+const K1: f64 = 1.2;
+/// How much longer documents are penalized (Okapi BM25's `b`).
+const B: f64 = 0.75;
+/// Words of context shown on either side of the first match.
+const SNIPPET_CONTEXT: usize = 6;
+
+/// Where the search index is written, relative to the build directory.
+pub(crate) const INDEX: &str = "search-index.json";
+
+/// Fetches the search index and lists the entries containing every word
+/// typed into `#search`, most occurrences first. Also runs the query in
+/// the page's `?q=` parameter, so other pages can link or submit to it.
+pub(crate) const SEARCH_SCRIPT: &str = r#"const input = document.getElementById("search");
+const results = document.getElementById("search-results");
+const index = fetch("/search-index.json").then((response) => response.json());
+const terms = (s) => s.toLowerCase().split(/[^\p{L}\p{N}]+/u).filter(Boolean);
+const run = async () => {
+  const query = terms(input.value);
+  const entries = await index;
+  results.replaceChildren();
+  if (query.length === 0) return;
+  const hits = entries
+    .map((entry) => {
+      const words = terms(entry.title + " " + entry.text);
+      const counts = query.map((term) => words.filter((word) => word === term).length);
+      return { entry, counts, score: counts.reduce((a, b) => a + b, 0) };
+    })
+    .filter((hit) => hit.counts.every((count) => count > 0))
+    .sort((a, b) => b.score - a.score);
+  for (const { entry } of hits) {
+    const link = Object.assign(document.createElement("a"), { href: entry.url, textContent: entry.title });
+    const item = document.createElement("li");
+    item.append(link, " (" + entry.date + ")");
+    results.append(item);
+  }
+  if (hits.length === 0) results.append(Object.assign(document.createElement("li"), { textContent: "No matches." }));
+};
+input.addEventListener("input", run);
+input.value = new URLSearchParams(location.search).get("q") ?? "";
+run();"#;
+
+/// Something that can be searched, such as a post.
+pub(crate) struct Document {
+    pub(crate) title: String,
+    pub(crate) date: chrono::NaiveDate,
+    /// Plain text of the body
+    pub(crate) text: String,
+}
+
+/// One post in the index the search page loads.
+#[derive(Serialize)]
+pub(crate) struct IndexEntry<'a> {
+    pub(crate) title: &'a str,
+    /// Path of the post, relative to the site root
+    pub(crate) url: String,
+    pub(crate) date: chrono::NaiveDate,
+    pub(crate) text: &'a str,
+}
+
+pub(crate) fn index_json(entries: &[IndexEntry]) -> serde_json::Result<String> {
+    serde_json::to_string(entries)
+}
+
+/// The search page: a text box, and a list its results are shown in.
+pub(crate) fn page() -> Markup {
+    html! {
+        h2 { "Search" }
+        form role="search" {
+            input id="search" type="search" name="q" aria-label="Search posts" autofocus;
+        }
+        ol id="search-results" {}
+        script { (PreEscaped(SEARCH_SCRIPT)) }
+        noscript { p { "Searching needs JavaScript." } }
+    }
+}
+
+pub(crate) struct Hit<'a> {
+    pub(crate) document: &'a Document,
+    pub(crate) score: f64,
+}
+
+/// Lowercased alphanumeric runs of `s`.
+fn terms(s: &str) -> impl Iterator<Item = String> + '_ {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Okapi BM25 scores over a set of documents' titles and text.
+struct Bm25 {
+    indexed: Vec<Vec<String>>,
+    average_len: f64,
+}
+
+impl Bm25 {
+    fn new(documents: &[Document]) -> Bm25 {
+        let indexed: Vec<Vec<String>> = documents
+            .iter()
+            .map(|document| {
+                terms(&document.title)
+                    .chain(terms(&document.text))
+                    .collect()
+            })
+            .collect();
+        let average_len =
+            indexed.iter().map(Vec::len).sum::<usize>() as f64 / indexed.len().max(1) as f64;
+        Bm25 {
+            indexed,
+            average_len,
+        }
+    }
+
+    fn contains_all(&self, document: usize, query: &[String]) -> bool {
+        query
+            .iter()
+            .all(|term| self.indexed[document].contains(term))
+    }
+
+    /// How well document number `document` matches `query`; 0 when it contains none of its terms.
+    fn score(&self, document: usize, query: &[String]) -> f64 {
+        let doc = &self.indexed[document];
+        let len_norm = 1.0 - B + B * doc.len() as f64 / self.average_len;
+        query
+            .iter()
+            .map(|term| {
+                let tf = doc.iter().filter(|t| *t == term).count() as f64;
+                self.idf(term) * tf * (K1 + 1.0) / (tf + K1 * len_norm)
+            })
+            .sum()
+    }
+
+    fn idf(&self, term: &String) -> f64 {
+        let containing = self.indexed.iter().filter(|doc| doc.contains(term)).count() as f64;
+        let n = self.indexed.len() as f64;
+        ((n - containing + 0.5) / (containing + 0.5) + 1.0).ln()
+    }
+}
+
+/// The documents containing every term of `query`, in its title or text,
+/// best match first by BM25.
+pub(crate) fn search<'a>(documents: &'a [Document], query: &str) -> Vec<Hit<'a>> {
+    let query: Vec<String> = terms(query).collect();
+    if query.is_empty() {
+        return vec![];
+    }
+
+    let bm25 = Bm25::new(documents);
+    let mut hits: Vec<Hit> = documents
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| bm25.contains_all(*i, &query))
+        .map(|(i, document)| Hit {
+            document,
+            score: bm25.score(i, &query),
+        })
+        .collect();
+
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits
+}
+
+/// For each document, the indices of up to `count` others that best match
+/// its title, best first. Documents sharing no title words aren't related.
+pub(crate) fn related(documents: &[Document], count: usize) -> Vec<Vec<usize>> {
+    let bm25 = Bm25::new(documents);
+    documents
+        .iter()
+        .enumerate()
+        .map(|(i, document)| {
+            let mut query: Vec<String> = terms(&document.title).collect();
+            query.sort_unstable();
+            query.dedup();
+
+            let mut scored: Vec<(usize, f64)> = (0..documents.len())
+                .filter(|other| *other != i)
+                .map(|other| (other, bm25.score(other, &query)))
+                .filter(|(_, score)| *score > 0.0)
+                .collect();
+            scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+            scored
+                .into_iter()
+                .take(count)
+                .map(|(other, _)| other)
+                .collect()
+        })
+        .collect()
+}
+
+/// A few words of `text` around the first word matching `query`, with the
+/// matching words wrapped in `before` and `after`.
+pub(crate) fn snippet(text: &str, query: &str, before: &str, after: &str) -> String {
+    let query: Vec<String> = terms(query).collect();
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let matches = |word: &str| terms(word).any(|term| query.contains(&term));
+
+    let first = words.iter().position(|word| matches(word)).unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_CONTEXT);
+    let end = (first + SNIPPET_CONTEXT + 1).min(words.len());
+
+    let mut snippet: Vec<String> = words[start..end]
+        .iter()
+        .map(|word| {
+            if matches(word) {
+                format!("{}{}{}", before, word, after)
+            } else {
+                word.to_string()
+            }
+        })
+        .collect();
+    if start > 0 {
+        snippet.insert(0, "…".to_string());
+    }
+    if end < words.len() {
+        snippet.push("…".to_string());
+    }
+    snippet.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Document;
+
+    #[test]
+    fn ranks_documents_matching_every_term() {
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let document = |title: &str, text: &str| Document {
+            title: title.to_string(),
+            date,
+            text: text.to_string(),
+        };
+        let documents = [
+            document("Rust", "Writing a static site generator in Rust."),
+            document("Gardening", "Tomatoes, mostly."),
+            document("Rust again", "Rust, Rust and more rust: a site about rust."),
+        ];
+
+        let hits = super::search(&documents, "RUST site");
+        let titles: Vec<&str> = hits.iter().map(|hit| hit.document.title.as_str()).collect();
+        assert_eq!(titles, ["Rust again", "Rust"]);
+        assert!(super::search(&documents, "rust tomatoes").is_empty());
+        assert_eq!(super::related(&documents, 3), [vec![2], vec![], vec![0]]);
+
+        assert_eq!(
+            super::snippet(
+                "one two three four five six seven eight nine, tomatoes ten",
+                "tomatoes",
+                "[",
+                "]"
+            ),
+            "… four five six seven eight nine, [tomatoes] ten"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::{anyhow, Result};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use tiny_http::{Header, Response, Server, StatusCode};
+
+/// Served by the dev server itself rather than written to the build directory.
+/// Pages built with live reload enabled load it.
+pub(crate) const LIVE_RELOAD_SCRIPT_PATH: &str = "/_stanley/live-reload.js";
+const LIVE_RELOAD_EVENTS_PATH: &str = "/_stanley/events";
+const LIVE_RELOAD_SCRIPT: &str =
+    r#"new EventSource("/_stanley/events").onmessage = () => location.reload();"#;
+
+/// Browsers waiting to hear that the site has been rebuilt.
+#[derive(Default)]
+pub(crate) struct LiveReload {
+    clients: Mutex<Vec<mpsc::Sender<()>>>,
+}
+
+impl LiveReload {
+    /// Tells every connected page to reload.
+    pub(crate) fn reload(&self) {
+        for client in self.clients.lock().expect("live reload lock").drain(..) {
+            let _ = client.send(());
+        }
+    }
+
+    fn subscribe(&self) -> mpsc::Receiver<()> {
+        let (sender, receiver) = mpsc::channel();
+        self.clients.lock().expect("live reload lock").push(sender);
+        receiver
+    }
+}
+
+/// A server-sent event stream that blocks until the next rebuild,
+/// sends a single event, and ends. The page reloads on that event.
+struct ReloadEvent {
+    rebuilt: Option<mpsc::Receiver<()>>,
+    event: std::io::Cursor<&'static [u8]>,
+}
+
+impl Read for ReloadEvent {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(rebuilt) = self.rebuilt.take() {
+            if rebuilt.recv().is_err() {
+                return Ok(0);
+            }
+        }
+        self.event.read(buf)
+    }
+}
+
+/// Serves the files in `build_dir` on localhost until interrupted.
+/// With `live_reload`, also serves the script and event stream that
+/// reload pages after a rebuild.
+pub(crate) fn serve(
+    build_dir: &Path,
+    port: u16,
+    live_reload: Option<Arc<LiveReload>>,
+) -> Result<()> {
+    let server = Server::http(("127.0.0.1", port)).map_err(|e| anyhow!(e))?;
+
+    println!("Serving {:?} at http://localhost:{}", build_dir, port);
+
+    for request in server.incoming_requests() {
+        if let Some(live_reload) = &live_reload {
+            match request.url() {
+                LIVE_RELOAD_SCRIPT_PATH => {
+                    let response = Response::from_string(LIVE_RELOAD_SCRIPT)
+                        .with_header(header("Content-Type", "text/javascript; charset=utf-8"));
+                    if let Err(e) = request.respond(response) {
+                        eprintln!("Could not send response: {}", e);
+                    }
+                    continue;
+                }
+                LIVE_RELOAD_EVENTS_PATH => {
+                    let event = ReloadEvent {
+                        rebuilt: Some(live_reload.subscribe()),
+                        event: std::io::Cursor::new(b"data: reload\n\n"),
+                    };
+                    // held open until the next rebuild, so don't block other requests on it
+                    std::thread::spawn(move || {
+                        let response = Response::new(
+                            StatusCode(200),
+                            vec![
+                                header("Content-Type", "text/event-stream"),
+                                header("Cache-Control", "no-cache"),
+                            ],
+                            event,
+                            None,
+                            None,
+                        );
+                        let _ = request.respond(response);
+                    });
+                    continue;
+                }
+                _ => (),
+            }
+        }
+
+        let response = match resolve(build_dir, request.url()) {
+            Some(path) => match std::fs::File::open(&path) {
+                Ok(file) => Response::from_file(file)
+                    .with_header(header("Content-Type", content_type(&path)))
+                    .boxed(),
+                Err(_) => not_found(build_dir),
+            },
+            None => not_found(build_dir),
+        };
+
+        let status = response.status_code().0;
+        println!("{} {} {}", request.method(), request.url(), status);
+
+        if let Err(e) = request.respond(response) {
+            eprintln!("Could not send response: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).expect("header names and values are ASCII")
+}
+
+/// The site's `404.html`, as hosts serve it, or plain text if there isn't one.
+fn not_found(build_dir: &Path) -> tiny_http::ResponseBox {
+    match std::fs::File::open(build_dir.join("404.html")) {
+        Ok(file) => Response::from_file(file)
+            .with_header(header("Content-Type", "text/html; charset=utf-8"))
+            .with_status_code(404)
+            .boxed(),
+        Err(_) => Response::from_string("Not Found")
+            .with_status_code(404)
+            .boxed(),
+    }
+}
+
+/// Maps a request URL to a file under `build_dir`, refusing anything that
+/// would escape it. Directories resolve to their `index.html`.
+fn resolve(build_dir: &Path, url: &str) -> Option<PathBuf> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let path = percent_encoding::percent_decode_str(path)
+        .decode_utf8()
+        .ok()?;
+
+    let mut resolved = build_dir.to_path_buf();
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+
+    if resolved.is_dir() {
+        resolved.push("index.html");
+    }
+
+    resolved.is_file().then_some(resolved)
+}
+
+pub(crate) fn content_type(path: &Path) -> &'static str {
+    if path.file_name().is_some_and(|name| name == "feed") {
+        return "application/rss+xml; charset=utf-8";
+    }
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        Some("txt") | Some("dot") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn refuses_to_leave_the_build_dir() {
+        let build_dir = std::env::temp_dir();
+
+        assert_eq!(super::resolve(&build_dir, "/../etc/passwd"), None);
+        assert_eq!(super::resolve(&build_dir, "/%2e%2e/etc/passwd"), None);
+    }
+}
@@ -0,0 +1,50 @@
+/// A `sitemap.xml` listing `urls`, each with the date it last changed if known.
+pub(crate) fn sitemap(urls: &[(String, Option<chrono::NaiveDate>)]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+
+    for (url, lastmod) in urls {
+        xml.push_str("  <url>\n    <loc>");
+        xml.push_str(&escape(url));
+        xml.push_str("</loc>\n");
+        if let Some(lastmod) = lastmod {
+            xml.push_str(&format!(
+                "    <lastmod>{}</lastmod>\n",
+                lastmod.format("%Y-%m-%d")
+            ));
+        }
+        xml.push_str("  </url>\n");
+    }
+
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn lists_urls_with_optional_lastmod() {
+        let xml = super::sitemap(&[
+            (
+                "https://example.com/a-post.html".to_string(),
+                chrono::NaiveDate::from_ymd_opt(2024, 3, 4),
+            ),
+            ("https://example.com/q&a.html".to_string(), None),
+        ]);
+
+        assert!(xml.contains(
+            "<url>\n    <loc>https://example.com/a-post.html</loc>\n    <lastmod>2024-03-04</lastmod>\n  </url>"
+        ));
+        assert!(xml.contains("<url>\n    <loc>https://example.com/q&amp;a.html</loc>\n  </url>"));
+    }
+}
@@ -0,0 +1,192 @@
+use crate::config::{Config, Link};
+use anyhow::{anyhow, Context, Result};
+use maud::{Markup, PreEscaped, Render};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// The template every page is rendered into, replacing the built-in layout.
+const LAYOUT: &str = "layout.html";
+
+/// Tera templates from the site's `templates/` directory.
+pub(crate) struct Templates {
+    tera: tera::Tera,
+    /// Hash of every template file
+    source_hash: String,
+}
+
+/// Only the hash is shown, which is enough for the build manifest to notice
+/// when a template changes.
+impl std::fmt::Debug for Templates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Templates")
+            .field("source_hash", &self.source_hash)
+            .finish()
+    }
+}
+
+#[derive(Serialize)]
+struct LayoutContext<'a> {
+    site: Site<'a>,
+    title: &'a str,
+    /// Elements the page needs in its `<head>`: the Content-Security-Policy,
+    /// stylesheets and scripts that stanley generates
+    head: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct Site<'a> {
+    title: &'a str,
+    description: &'a str,
+    base_url: &'a str,
+    nav: &'a [Link],
+    footer: &'a [Link],
+}
+
+impl Templates {
+    /// Loads every template in `dirs`, or returns `None` if there are none.
+    /// A template in a later dir replaces one with the same name in an
+    /// earlier dir, so a site's own templates override its theme's.
+    /// The layout is rendered once up front so that mistakes in it are
+    /// reported before anything is built.
+    pub(crate) fn load(dirs: &[PathBuf], config: &Config) -> Result<Option<Templates>> {
+        let files = crate::assets::layered_files(dirs)?;
+        if files.is_empty() {
+            return Ok(None);
+        }
+
+        let mut named = Vec::with_capacity(files.len());
+        let mut hashed = Vec::with_capacity(files.len());
+        for (relative, path) in &files {
+            // template names always use `/`, as in `{% include "partials/nav.html" %}`
+            let name = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let contents =
+                std::fs::read(path).with_context(|| format!("Could not read {:?}", path))?;
+            hashed.push(name.clone().into_bytes());
+            hashed.push(contents);
+            named.push((path, Some(name)));
+        }
+
+        let mut tera = tera::Tera::default();
+        tera.add_template_files(named)
+            .context("Could not load templates")?;
+
+        let layout = files
+            .get(Path::new(LAYOUT))
+            .ok_or_else(|| anyhow!("No {} in {:?}", LAYOUT, dirs))?;
+
+        let templates = Templates {
+            tera,
+            source_hash: crate::manifest::hash(
+                &hashed.iter().map(Vec::as_slice).collect::<Vec<_>>(),
+            ),
+        };
+        templates
+            .render_layout(config, "", "", "")
+            .with_context(|| format!("Could not render {:?}", layout))?;
+
+        Ok(Some(templates))
+    }
+
+    /// `content` inside the site's layout template.
+    pub(crate) fn layout(
+        &self,
+        config: &Config,
+        title: &str,
+        head: Markup,
+        content: impl Render,
+    ) -> Result<Markup> {
+        let html = self
+            .render_layout(config, title, &head.0, &content.render().0)
+            .with_context(|| format!("Could not render {} for {:?}", LAYOUT, title))?;
+        Ok(PreEscaped(html))
+    }
+
+    fn render_layout(
+        &self,
+        config: &Config,
+        title: &str,
+        head: &str,
+        content: &str,
+    ) -> Result<String> {
+        let context = LayoutContext {
+            site: Site {
+                title: &config.title,
+                description: &config.description,
+                base_url: &config.base_url,
+                nav: &config.nav,
+                footer: &config.footer,
+            },
+            title,
+            head,
+            content,
+        };
+
+        Ok(self
+            .tera
+            .render(LAYOUT, &tera::Context::from_serialize(context)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Templates;
+    use crate::config::Config;
+
+    #[test]
+    fn renders_pages_into_the_layout() {
+        let dir = crate::test_dir::TestDir::new("templates");
+        let theme_dir = dir.join("themes/plain/templates");
+        let site_dir = dir.join("templates");
+        std::fs::create_dir_all(theme_dir.join("partials")).unwrap();
+        std::fs::create_dir_all(site_dir.join("partials")).unwrap();
+        std::fs::write(
+            theme_dir.join("layout.html"),
+            "<title>{{ title }} | {{ site.title }}</title>{{ head | safe }}{% include \"partials/main.html\" %}",
+        )
+        .unwrap();
+        std::fs::write(theme_dir.join("partials/main.html"), "theme").unwrap();
+        std::fs::write(
+            site_dir.join("partials/main.html"),
+            "<main>{{ content | safe }}</main>",
+        )
+        .unwrap();
+        let dirs = [theme_dir.clone(), site_dir];
+
+        let config = Config::default();
+        let templates = Templates::load(&dirs, &config).unwrap().unwrap();
+        let page = templates.layout(
+            &config,
+            "A & B",
+            maud::html! { meta name="x"; },
+            maud::html! { p { "hi" } },
+        );
+
+        assert_eq!(
+            page.unwrap().0,
+            "<title>A &amp; B | stanley</title><meta name=\"x\"><main><p>hi</p></main>"
+        );
+
+        // fails only for some pages, so it loads
+        std::fs::write(
+            theme_dir.join("layout.html"),
+            "{% if title == \"Broken\" %}{{ missing }}{% endif %}",
+        )
+        .unwrap();
+        let templates = Templates::load(&dirs, &config).unwrap().unwrap();
+        let error = templates
+            .layout(&config, "Broken", maud::html! {}, maud::html! {})
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Could not render layout.html for \"Broken\""
+        );
+
+        std::fs::write(theme_dir.join("layout.html"), "{{ missing }}").unwrap();
+        assert!(Templates::load(&dirs, &config).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use std::path::{Path, PathBuf};
+
+/// `html` with every relative `href`, `src` and `srcset` URL made absolute, resolved
+/// against `page_url`, the absolute URL of the page the HTML appears on.
+/// For HTML that's read somewhere else, like a feed reader.
+pub(crate) fn absolutize(html: &str, page_url: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some((before, attribute, quote, after)) = next_attribute(rest) {
+        out.push_str(before);
+        out.push_str(attribute);
+        out.push(quote);
+        let Some(end) = after.find(quote) else {
+            rest = after;
+            break;
+        };
+        let value = &after[..end];
+        if attribute == "srcset=" {
+            // comma-separated candidates, each a URL and maybe a descriptor like `480w`
+            let candidates: Vec<String> = value
+                .split(',')
+                .map(|candidate| {
+                    let candidate = candidate.trim();
+                    match candidate.split_once(char::is_whitespace) {
+                        Some((url, descriptor)) => {
+                            format!("{} {}", resolve(page_url, url), descriptor.trim())
+                        }
+                        None => resolve(page_url, candidate),
+                    }
+                })
+                .collect();
+            out.push_str(&candidates.join(", "));
+        } else {
+            out.push_str(&resolve(page_url, value));
+        }
+        rest = &after[end..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Splits `html` around the next ` href=`, ` src=` or ` srcset=` with a quoted value:
+/// (text before, the attribute up to `=`, the quote, text after the quote).
+fn next_attribute(html: &str) -> Option<(&str, &str, char, &str)> {
+    let mut from = 0;
+    loop {
+        let start = ["href=", "src=", "srcset="]
+            .iter()
+            .filter_map(|name| html[from..].find(name).map(|i| (from + i, name.len())))
+            .min()?;
+        let (i, len) = start;
+        let preceded_by_space = html[..i].ends_with(|c: char| c.is_ascii_whitespace());
+        let quote = html[i + len..].chars().next();
+        match quote {
+            Some(quote @ ('"' | '\'')) if preceded_by_space => {
+                return Some((&html[..i], &html[i..i + len], quote, &html[i + len + 1..]))
+            }
+            _ => from = i + len,
+        }
+    }
+}
+
+/// The file in `build_dir` that `link`, on the page at `page_url`, points at,
+/// if it's a file on this site, at `base_url`.
+pub(crate) fn local_file(
+    build_dir: &Path,
+    base_url: &str,
+    page_url: &str,
+    link: &str,
+) -> Option<PathBuf> {
+    let url = resolve(page_url, link);
+    let path = url.strip_prefix(base_url)?.split(['?', '#']).next()?;
+    let path = percent_encoding::percent_decode_str(path)
+        .decode_utf8()
+        .ok()?;
+    let file = build_dir.join(path.trim_start_matches('/'));
+    file.is_file().then_some(file)
+}
+
+/// `link` as an absolute URL, as a browser on `page_url` would resolve it.
+fn resolve(page_url: &str, link: &str) -> String {
+    let has_scheme = link.split_once(':').is_some_and(|(scheme, _)| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    });
+    if link.is_empty() || has_scheme || link.starts_with("//") {
+        return link.to_string();
+    }
+
+    let page_url = page_url.split('#').next().unwrap_or(page_url);
+    if link.starts_with('#') {
+        return format!("{}{}", page_url, link);
+    }
+
+    // "https://example.com" of "https://example.com/a/b.html"
+    let after_scheme = page_url.find("://").map_or(0, |i| i + 3);
+    let origin_end = page_url[after_scheme..]
+        .find('/')
+        .map_or(page_url.len(), |i| after_scheme + i);
+    let origin = &page_url[..origin_end];
+
+    let path = if link.starts_with('/') {
+        link.to_string()
+    } else {
+        let page_path = page_url[origin_end..].split('?').next().unwrap_or_default();
+        let dir = &page_path[..page_path.rfind('/').map_or(0, |i| i + 1)];
+        format!("{}{}", if dir.is_empty() { "/" } else { dir }, link)
+    };
+
+    format!("{}{}", origin, remove_dot_segments(&path))
+}
+
+/// Resolves `.` and `..` segments in an absolute path.
+fn remove_dot_segments(path: &str) -> String {
+    let (path, suffix) = match path.find(['?', '#']) {
+        Some(i) => path.split_at(i),
+        None => (path, ""),
+    };
+
+    let mut segments: Vec<&str> = vec![];
+    let mut parts = path.split('/').skip(1).peekable();
+    while let Some(part) = parts.next() {
+        match part {
+            "." => (),
+            ".." => {
+                segments.pop();
+            }
+            part => segments.push(part),
+        }
+        // "a/." and "a/.." name a directory, so keep the trailing slash
+        if parts.peek().is_none() && matches!(part, "." | "..") {
+            segments.push("");
+        }
+    }
+
+    format!("/{}{}", segments.join("/"), suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn makes_relative_urls_absolute() {
+        let page = "https://example.com/2024/hello/index.html";
+
+        assert_eq!(
+            super::resolve(page, "cat.png"),
+            "https://example.com/2024/hello/cat.png"
+        );
+        assert_eq!(
+            super::resolve(page, "../other/"),
+            "https://example.com/2024/other/"
+        );
+        assert_eq!(
+            super::resolve(page, "/about.html"),
+            "https://example.com/about.html"
+        );
+        assert_eq!(super::resolve(page, "#intro"), format!("{}#intro", page));
+        assert_eq!(
+            super::resolve(page, "mailto:me@example.com"),
+            "mailto:me@example.com"
+        );
+        assert_eq!(
+            super::resolve(page, "//cdn.example.com/x.js"),
+            "//cdn.example.com/x.js"
+        );
+
+        assert_eq!(
+            super::absolutize(
+                "<p><a href=\"/a.html\">a</a> <img src='cat.png' alt=\"href=x\"> <a href=\"https://b.com\">b</a></p>",
+                page
+            ),
+            "<p><a href=\"https://example.com/a.html\">a</a> <img src='https://example.com/2024/hello/cat.png' alt=\"href=x\"> <a href=\"https://b.com\">b</a></p>"
+        );
+        assert_eq!(
+            super::absolutize("<img srcset=\"cat-480w.png 480w, /cat.png 1200w\">", page),
+            "<img srcset=\"https://example.com/2024/hello/cat-480w.png 480w, https://example.com/cat.png 1200w\">"
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Site sources that trigger a rebuild when they change, relative to the site directory.
+/// The build directory is deliberately not among them.
+const WATCHED: &[&str] = &[
+    "posts",
+    "pages",
+    "static",
+    "templates",
+    "themes",
+    "comments",
+    "plugins",
+    "stanley.toml",
+    "webmentions.json",
+];
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Calls `on_change` whenever a source file is added, removed or modified.
+/// Polls modification times rather than relying on OS notifications,
+/// so it behaves the same everywhere. Changes `on_change` makes itself, like
+/// a build hook writing to `static/`, don't count. Never returns.
+pub(crate) fn watch(site_dir: &Path, mut on_change: impl FnMut()) -> ! {
+    println!("Watching {:?} for changes", site_dir);
+    let mut last = snapshot(site_dir);
+
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let current = snapshot(site_dir);
+        if current != last {
+            on_change();
+            last = snapshot(site_dir);
+        }
+    }
+}
+
+/// Modification time of every watched file.
+fn snapshot(site_dir: &Path) -> BTreeMap<PathBuf, SystemTime> {
+    let mut files = BTreeMap::new();
+    for watched in WATCHED {
+        visit(&site_dir.join(watched), &mut files);
+    }
+    files
+}
+
+fn visit(path: &Path, files: &mut BTreeMap<PathBuf, SystemTime>) {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return;
+    };
+
+    if metadata.is_dir() {
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            visit(&entry.path(), files);
+        }
+    } else if let Ok(modified) = metadata.modified() {
+        files.insert(path.to_path_buf(), modified);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn snapshot_sees_new_sources_but_not_output() {
+        let site_dir = crate::test_dir::TestDir::new("watch");
+        std::fs::create_dir_all(site_dir.join("posts")).unwrap();
+        std::fs::create_dir_all(site_dir.join("build")).unwrap();
+        std::fs::write(site_dir.join("posts/a.md"), "a").unwrap();
+
+        let before = super::snapshot(&site_dir);
+        std::fs::write(site_dir.join("build/a.html"), "a").unwrap();
+        assert_eq!(before, super::snapshot(&site_dir));
+
+        std::fs::write(site_dir.join("posts/b.md"), "b").unwrap();
+        assert_ne!(before, super::snapshot(&site_dir));
+    }
+}
@@ -0,0 +1,644 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// Name, under `.cache/`, of the record of which webmentions have been sent.
+pub(crate) const SENT: &str = "webmentions-sent.json";
+
+/// Each post's outbound links as of the last build, and the webmentions
+/// already sent for them, so a mention is only sent again when the post
+/// changes.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct Mentions {
+    /// Post permalink -> its links
+    sources: BTreeMap<String, Source>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Source {
+    /// Hash of the post's content as last built
+    hash: String,
+    /// Pages on other sites the post links to
+    links: BTreeSet<String>,
+    /// Target -> hash of the post's content when it was last sent a mention
+    sent: BTreeMap<String, String>,
+}
+
+impl Mentions {
+    /// Reads the record at `path`. A missing or unreadable record is treated
+    /// as empty, which means mentions are sent again.
+    pub(crate) fn load(path: &Path) -> Mentions {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create {:?}", parent))?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Could not write webmention record {:?}", path))
+    }
+
+    /// Replaces the posts and links recorded with `posts`: permalink ->
+    /// (content hash, links). Targets of posts that have gone away are still
+    /// sent a last mention, so they can see the post is gone.
+    pub(crate) fn update(&mut self, posts: BTreeMap<String, (String, BTreeSet<String>)>) {
+        for (url, source) in &mut self.sources {
+            if !posts.contains_key(url) {
+                source.hash = "removed".to_string();
+                source.links.clear();
+            }
+        }
+        for (url, (hash, links)) in posts {
+            let source = self.sources.entry(url).or_default();
+            source.hash = hash;
+            source.links = links;
+        }
+        self.sources
+            .retain(|_, source| !source.links.is_empty() || !source.sent.is_empty());
+    }
+
+    /// (source, target) pairs to send mentions for: links added or in posts
+    /// changed since they were last sent, and links since removed from them.
+    fn due(&self) -> Vec<(String, String)> {
+        self.sources
+            .iter()
+            .flat_map(|(url, source)| {
+                source
+                    .links
+                    .iter()
+                    .chain(source.sent.keys())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .filter(|target| source.sent.get(*target) != Some(&source.hash))
+                    .map(|target| (url.clone(), target.clone()))
+            })
+            .collect()
+    }
+
+    fn mark_sent(&mut self, url: &str, target: &str) {
+        if let Some(source) = self.sources.get_mut(url) {
+            if source.links.contains(target) {
+                source.sent.insert(target.to_string(), source.hash.clone());
+            } else {
+                source.sent.remove(target);
+            }
+        }
+    }
+}
+
+/// Absolute `http(s)` links in `html` that aren't to pages under `base_url`.
+pub(crate) fn outbound_links(html: &str, base_url: &str) -> BTreeSet<String> {
+    crate::images::tags(html, "a")
+        .into_iter()
+        .filter_map(|tag| crate::images::attribute(tag, "href"))
+        .map(|href| href.replace("&amp;", "&"))
+        .filter(|href| href.starts_with("https://") || href.starts_with("http://"))
+        .filter(|href| !href.starts_with(base_url))
+        .collect()
+}
+
+/// Sends the webmentions due in the record at `path`, saving it as each is
+/// sent. A target that can't be reached is tried again next time rather
+/// than failing the build.
+pub(crate) fn send_due(path: &Path) -> Result<()> {
+    let mut mentions = Mentions::load(path);
+    let due = mentions.due();
+    let mut sent = 0;
+    for (source, target) in &due {
+        match send(source, target) {
+            Ok(endpoint) => {
+                if let Some(endpoint) = endpoint {
+                    println!("Sent a webmention for {} to {}", target, endpoint);
+                    sent += 1;
+                }
+                mentions.mark_sent(source, target);
+                mentions.save(path)?;
+            }
+            Err(e) => eprintln!("Could not send a webmention to {}: {:#}", target, e),
+        }
+    }
+    if !due.is_empty() {
+        println!("Sent {} webmentions for {} links", sent, due.len());
+    }
+    Ok(())
+}
+
+/// Tells `target`'s webmention endpoint, if it has one, that `source` links
+/// to it, returning the endpoint.
+fn send(source: &str, target: &str) -> Result<Option<String>> {
+    let Some(endpoint) = discover(target)? else {
+        return Ok(None);
+    };
+    ureq::post(&endpoint)
+        .send_form(&[("source", source), ("target", target)])
+        .with_context(|| format!("Could not send a webmention to {}", endpoint))?;
+    Ok(Some(endpoint))
+}
+
+/// The webmention endpoint `target` advertises, in a `Link` header or a
+/// `<link>` or `<a>` with `rel="webmention"`.
+fn discover(target: &str) -> Result<Option<String>> {
+    // ureq's errors name the URL
+    let response = ureq::get(target).call()?;
+    let url = response.get_url().to_string();
+    if let Some(endpoint) = response
+        .all("Link")
+        .into_iter()
+        .find_map(endpoint_from_link_header)
+    {
+        return Ok(Some(resolve(&url, &endpoint)));
+    }
+    let is_html = response
+        .header("Content-Type")
+        .is_some_and(|content_type| content_type.contains("html"));
+    if !is_html {
+        return Ok(None);
+    }
+    let html = response
+        .into_string()
+        .with_context(|| format!("Could not read {}", target))?;
+    Ok(endpoint_from_html(&html).map(|endpoint| resolve(&url, &endpoint)))
+}
+
+/// The first URL in a `Link` header value with `webmention` among its rels,
+/// e.g. `<https://example.com/webmention>; rel="webmention"`.
+fn endpoint_from_link_header(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let (url, params) = link.trim().strip_prefix('<')?.split_once('>')?;
+        params
+            .split(';')
+            .filter_map(|param| param.trim().strip_prefix("rel="))
+            .any(|rels| has_webmention_rel(rels.trim_matches('"')))
+            .then(|| url.to_string())
+    })
+}
+
+/// The `href` of the first `<link>` or `<a>` in `html` with `webmention` among its rels.
+fn endpoint_from_html(html: &str) -> Option<String> {
+    let mut tags: Vec<&str> = crate::images::tags(html, "link");
+    tags.extend(crate::images::tags(html, "a"));
+    // the first in the document wins, whichever element it is
+    tags.sort_by_key(|tag| tag.as_ptr());
+    tags.into_iter()
+        .filter(|tag| crate::images::attribute(tag, "rel").is_some_and(has_webmention_rel))
+        .find_map(|tag| crate::images::attribute(tag, "href"))
+        .map(|href| href.replace("&amp;", "&"))
+}
+
+fn has_webmention_rel(rels: &str) -> bool {
+    rels.split_ascii_whitespace()
+        .any(|rel| rel.eq_ignore_ascii_case("webmention"))
+}
+
+/// `href` resolved against the absolute URL `base`.
+fn resolve(base: &str, href: &str) -> String {
+    let (scheme, after_scheme) = base.split_once("://").unwrap_or(("https", base));
+    let origin_len = scheme.len() + 3 + after_scheme.find('/').unwrap_or(after_scheme.len());
+    let base_path = base.split(['?', '#']).next().unwrap_or(base);
+    if href.contains("://") {
+        href.to_string()
+    } else if let Some(network_path) = href.strip_prefix("//") {
+        format!("{}://{}", scheme, network_path)
+    } else if href.starts_with('/') {
+        format!("{}{}", &base[..origin_len], href)
+    } else if href.is_empty() {
+        base.to_string()
+    } else if href.starts_with('?') {
+        format!("{}{}", base_path, href)
+    } else {
+        let dir = match base_path[origin_len..].rfind('/') {
+            Some(slash) => &base_path[..origin_len + slash + 1],
+            None => &base[..origin_len],
+        };
+        let separator = if dir.ends_with('/') { "" } else { "/" };
+        format!("{}{}{}", dir, separator, href)
+    }
+}
+
+/// Name, in the site directory, of the file `webmentions fetch` keeps the
+/// webmentions the site has received in.
+const RECEIVED: &str = "webmentions.json";
+
+/// How many mentions to ask webmention.io for at a time.
+const PAGE_SIZE: usize = 100;
+
+/// A webmention received for one of the site's posts.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub(crate) struct Mention {
+    /// webmention.io's id for it, which increases with each one received
+    pub(crate) id: u64,
+    /// The page that mentions the post
+    pub(crate) source: String,
+    /// The post mentioned
+    pub(crate) target: String,
+    pub(crate) kind: Kind,
+    pub(crate) author: String,
+    pub(crate) author_url: String,
+    /// Canonical URL of the mentioning post, which may differ from `source`
+    pub(crate) url: String,
+    pub(crate) published: String,
+    /// Plain text; remote HTML is never rendered as-is.
+    pub(crate) text: String,
+}
+
+/// What the mentioning page does with the post.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Kind {
+    Reply,
+    Like,
+    Repost,
+    Bookmark,
+    Mention,
+}
+
+/// The webmentions received so far, oldest first.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct Received {
+    pub(crate) mentions: Vec<Mention>,
+}
+
+impl Received {
+    /// Reads the mentions in `site_dir`, or none if it has none.
+    pub(crate) fn load(site_dir: &Path) -> Result<Received> {
+        let path = site_dir.join(RECEIVED);
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Could not parse webmentions {:?}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Received::default()),
+            Err(e) => Err(e).with_context(|| format!("Could not read webmentions {:?}", path)),
+        }
+    }
+
+    fn save(&self, site_dir: &Path) -> Result<()> {
+        let path = site_dir.join(RECEIVED);
+        std::fs::write(&path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("Could not write webmentions {:?}", path))
+    }
+
+    /// Adds `mentions`, replacing any already received with the same id.
+    fn merge(&mut self, mentions: Vec<Mention>) {
+        for mention in mentions {
+            self.mentions.retain(|received| received.id != mention.id);
+            self.mentions.push(mention);
+        }
+        self.mentions.sort_by_key(|mention| mention.id);
+    }
+
+    /// The mentions of the post at `permalink`, ignoring any `#fragment` of their target.
+    pub(crate) fn of<'a>(&'a self, permalink: &str) -> Vec<&'a Mention> {
+        self.mentions
+            .iter()
+            .filter(|mention| mention.target.split('#').next() == Some(permalink))
+            .collect()
+    }
+}
+
+/// Downloads the webmentions webmention.io has received for the site since
+/// the last fetch, using the API token in `WEBMENTION_IO_TOKEN`, and adds
+/// them to the site's `webmentions.json`.
+pub(crate) fn fetch(site_dir: &Path) -> Result<()> {
+    let token = std::env::var("WEBMENTION_IO_TOKEN")
+        .context("Could not read WEBMENTION_IO_TOKEN from the environment")?;
+    let mut received = Received::load(site_dir)?;
+    let since_id = received.mentions.iter().map(|mention| mention.id).max();
+
+    let mut fetched = vec![];
+    for page in 0.. {
+        let mut request = ureq::get("https://webmention.io/api/mentions.jf2")
+            .query("token", &token)
+            .query("per-page", &PAGE_SIZE.to_string())
+            .query("page", &page.to_string());
+        if let Some(since_id) = since_id {
+            request = request.query("since_id", &since_id.to_string());
+        }
+        // errors are reported without the URL, which has the token in it
+        let response = request.call().map_err(|error| match error {
+            ureq::Error::Status(status, _) => {
+                anyhow!("webmention.io responded with status {}", status)
+            }
+            ureq::Error::Transport(transport) => {
+                anyhow!("Could not reach webmention.io: {}", transport.kind())
+            }
+        })?;
+        let mentions = parse_jf2(&response.into_string()?)?;
+        let done = mentions.len() < PAGE_SIZE;
+        fetched.extend(mentions);
+        if done {
+            break;
+        }
+    }
+
+    println!("Fetched {} new webmentions", fetched.len());
+    received.merge(fetched);
+    received.save(site_dir)
+}
+
+#[derive(Deserialize)]
+struct Jf2Feed {
+    children: Vec<Jf2Entry>,
+}
+
+#[derive(Deserialize)]
+struct Jf2Entry {
+    #[serde(rename = "wm-id")]
+    id: u64,
+    #[serde(rename = "wm-source")]
+    source: String,
+    #[serde(rename = "wm-target")]
+    target: String,
+    #[serde(rename = "wm-property")]
+    property: String,
+    #[serde(rename = "wm-private", default)]
+    private: bool,
+    #[serde(rename = "wm-received")]
+    received: String,
+    author: Option<Jf2Author>,
+    url: Option<String>,
+    published: Option<String>,
+    content: Option<Jf2Content>,
+}
+
+#[derive(Deserialize)]
+struct Jf2Author {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct Jf2Content {
+    #[serde(default)]
+    text: String,
+}
+
+/// The public mentions in a webmention.io JF2 feed.
+fn parse_jf2(json: &str) -> Result<Vec<Mention>> {
+    let feed: Jf2Feed = serde_json::from_str(json)?;
+
+    Ok(feed
+        .children
+        .into_iter()
+        .filter(|entry| !entry.private && is_web_url(&entry.source))
+        .map(|entry| {
+            let (author, author_url) = match entry.author {
+                Some(author) if !author.name.is_empty() => (
+                    author.name,
+                    Some(author.url)
+                        .filter(|url| is_web_url(url))
+                        .unwrap_or_else(|| entry.source.clone()),
+                ),
+                // anonymous mentions are credited to the site they came from
+                _ => (host(&entry.source).to_string(), entry.source.clone()),
+            };
+            Mention {
+                id: entry.id,
+                kind: match entry.property.as_str() {
+                    "in-reply-to" => Kind::Reply,
+                    "like-of" => Kind::Like,
+                    "repost-of" => Kind::Repost,
+                    "bookmark-of" => Kind::Bookmark,
+                    _ => Kind::Mention,
+                },
+                author,
+                author_url,
+                url: entry
+                    .url
+                    .filter(|url| is_web_url(url))
+                    .unwrap_or_else(|| entry.source.clone()),
+                published: entry
+                    .published
+                    .unwrap_or(entry.received)
+                    .chars()
+                    .take(10)
+                    .collect(),
+                text: entry
+                    .content
+                    .map(|content| content.text)
+                    .unwrap_or_default(),
+                source: entry.source,
+                target: entry.target,
+            }
+        })
+        .collect())
+}
+
+/// Whether `url` is an http or https URL, and so safe to link to; senders
+/// control the URLs in a mention, and could send `javascript:` ones.
+pub(crate) fn is_web_url(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+fn host(url: &str) -> &str {
+    let after_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    after_scheme.split('/').next().unwrap_or(after_scheme)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[test]
+    fn parses_webmention_io_feeds() {
+        let json = r#"{
+            "type": "feed",
+            "name": "Webmentions",
+            "children": [
+                {
+                    "type": "entry",
+                    "author": {"type": "card", "name": "Ann", "photo": "", "url": "https://ann.example/"},
+                    "url": "https://ann.example/replies/1",
+                    "published": "2024-05-07T10:00:00+00:00",
+                    "wm-received": "2024-05-07T10:05:00Z",
+                    "wm-id": 12,
+                    "wm-source": "https://ann.example/replies/1",
+                    "wm-target": "https://me.example/post.html#comments",
+                    "content": {"html": "<p>Nice <b>post</b></p>", "text": "Nice post"},
+                    "in-reply-to": "https://me.example/post.html",
+                    "wm-property": "in-reply-to",
+                    "wm-private": false
+                },
+                {
+                    "type": "entry",
+                    "author": {"type": "card", "name": "", "photo": "", "url": ""},
+                    "url": null,
+                    "published": null,
+                    "wm-received": "2024-05-08T00:00:00Z",
+                    "wm-id": 13,
+                    "wm-source": "https://bob.example/likes/2",
+                    "wm-target": "https://me.example/post.html",
+                    "wm-property": "like-of"
+                },
+                {
+                    "type": "entry",
+                    "wm-received": "2024-05-09T00:00:00Z",
+                    "wm-id": 14,
+                    "wm-source": "https://private.example/",
+                    "wm-target": "https://me.example/post.html",
+                    "wm-property": "mention-of",
+                    "wm-private": true
+                }
+            ]
+        }"#;
+        let mentions = super::parse_jf2(json).unwrap();
+        assert_eq!(
+            mentions[0],
+            super::Mention {
+                id: 12,
+                source: "https://ann.example/replies/1".to_string(),
+                target: "https://me.example/post.html#comments".to_string(),
+                kind: super::Kind::Reply,
+                author: "Ann".to_string(),
+                author_url: "https://ann.example/".to_string(),
+                url: "https://ann.example/replies/1".to_string(),
+                published: "2024-05-07".to_string(),
+                text: "Nice post".to_string(),
+            }
+        );
+        assert_eq!(mentions[1].kind, super::Kind::Like);
+        assert_eq!(mentions[1].author, "bob.example");
+        assert_eq!(mentions[1].url, "https://bob.example/likes/2");
+        assert_eq!(mentions[1].published, "2024-05-08");
+        assert_eq!(mentions.len(), 2);
+
+        let mut received = super::Received::default();
+        received.merge(mentions);
+        assert_eq!(received.of("https://me.example/post.html").len(), 2);
+        assert!(received.of("https://me.example/other.html").is_empty());
+    }
+
+    #[test]
+    fn only_keeps_web_urls_from_senders() {
+        let json = r#"{
+            "children": [
+                {
+                    "author": {"name": "Eve", "url": "javascript:alert(1)"},
+                    "url": "JavaScript:alert(2)",
+                    "wm-received": "2024-05-07T00:00:00Z",
+                    "wm-id": 1,
+                    "wm-source": "https://eve.example/1",
+                    "wm-target": "https://me.example/post.html",
+                    "wm-property": "in-reply-to"
+                },
+                {
+                    "wm-received": "2024-05-08T00:00:00Z",
+                    "wm-id": 2,
+                    "wm-source": "data:text/html,hi",
+                    "wm-target": "https://me.example/post.html",
+                    "wm-property": "mention-of"
+                }
+            ]
+        }"#;
+        let mentions = super::parse_jf2(json).unwrap();
+        assert_eq!(mentions.len(), 1);
+        assert_eq!(mentions[0].author, "Eve");
+        assert_eq!(mentions[0].author_url, "https://eve.example/1");
+        assert_eq!(mentions[0].url, "https://eve.example/1");
+
+        assert!(super::is_web_url("HTTPS://a.example/"));
+        assert!(!super::is_web_url(" javascript:alert(1)"));
+    }
+
+    #[test]
+    fn finds_outbound_links() {
+        let html = r#"<p><a href="https://example.com/a?x=1&amp;y=2">a</a>
+            <a href="/local.html">b</a> <a href="https://me.example/post.html">c</a>
+            <a href="mailto:me@example.com">d</a></p>"#;
+        assert_eq!(
+            super::outbound_links(html, "https://me.example"),
+            BTreeSet::from(["https://example.com/a?x=1&y=2".to_string()])
+        );
+    }
+
+    #[test]
+    fn discovers_endpoints() {
+        assert_eq!(
+            super::endpoint_from_link_header(
+                r#"<https://a.example/style.css>; rel="preload", </mention>; rel="other webmention""#
+            ),
+            Some("/mention".to_string())
+        );
+        assert_eq!(
+            super::endpoint_from_html(
+                r#"<a href="/x" rel="me">me</a><a rel="webmention" href="/a">a</a>
+                   <link rel="webmention" href="/link">"#
+            ),
+            Some("/a".to_string())
+        );
+        assert_eq!(super::endpoint_from_html("<a href=\"/x\">x</a>"), None);
+
+        let base = "https://a.example/posts/one.html?x=1";
+        assert_eq!(
+            super::resolve(base, "https://b.example/wm"),
+            "https://b.example/wm"
+        );
+        assert_eq!(
+            super::resolve(base, "//b.example/wm"),
+            "https://b.example/wm"
+        );
+        assert_eq!(super::resolve(base, "/wm"), "https://a.example/wm");
+        assert_eq!(super::resolve(base, "wm"), "https://a.example/posts/wm");
+        assert_eq!(
+            super::resolve(base, "?wm=1"),
+            "https://a.example/posts/one.html?wm=1"
+        );
+        assert_eq!(super::resolve(base, ""), base);
+        assert_eq!(
+            super::resolve("https://a.example", "wm"),
+            "https://a.example/wm"
+        );
+    }
+
+    #[test]
+    fn sends_mentions_for_new_changed_and_removed_links() {
+        let links = |links: &[&str]| links.iter().map(|link| link.to_string()).collect();
+        let mut mentions = super::Mentions::default();
+        mentions.update(BTreeMap::from([(
+            "https://me.example/post.html".to_string(),
+            (
+                "1".to_string(),
+                links(&["https://a.example", "https://b.example"]),
+            ),
+        )]));
+        let due = mentions.due();
+        assert_eq!(due.len(), 2);
+        for (source, target) in &due {
+            mentions.mark_sent(source, target);
+        }
+        assert!(mentions.due().is_empty());
+
+        // the post changed and lost a link
+        mentions.update(BTreeMap::from([(
+            "https://me.example/post.html".to_string(),
+            ("2".to_string(), links(&["https://a.example"])),
+        )]));
+        let due = mentions.due();
+        assert_eq!(due.len(), 2);
+        for (source, target) in &due {
+            mentions.mark_sent(source, target);
+        }
+        assert!(mentions.due().is_empty());
+
+        // the post is gone
+        mentions.update(BTreeMap::new());
+        let due = mentions.due();
+        assert_eq!(
+            due,
+            [(
+                "https://me.example/post.html".to_string(),
+                "https://a.example".to_string()
+            )]
+        );
+        mentions.mark_sent(&due[0].0, &due[0].1);
+        mentions.update(BTreeMap::new());
+        assert!(mentions.sources.is_empty());
+    }
+}